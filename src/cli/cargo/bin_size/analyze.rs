@@ -0,0 +1,568 @@
+//! Native binary size analyzer, which does not require `cargo bloat`.
+
+use std::{fmt, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use hstr::Atom;
+use object::{Object, ObjectSection, ObjectSymbol, SectionIndex, SectionKind, SymbolKind};
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+
+use super::{
+    bloat::{check_bloat_args, run_bloat, BloatCrate},
+    OptLevel,
+};
+use crate::util::{
+    cancellable,
+    cargo_build::{compile_with, BinFile, CargoBuildTarget, DiagnosticsJson},
+    ensure_cargo_subcommand,
+};
+
+/// Name used by `cargo bloat` for symbols which are not from a Rust crate.
+const UNKNOWN_CRATE: &str = "[Unknown]";
+
+/// Tool used to measure the size of each crate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Analyzer {
+    /// Read the symbols of the built binary.
+    #[default]
+    Native,
+    /// Use `cargo bloat`, which should be installed.
+    CargoBloat,
+}
+
+/// Rejects the options which do not select a binary for `analyzer`, so it's
+/// reported before anything is built.
+pub(crate) fn check_build_target(
+    analyzer: Analyzer,
+    build_target: &CargoBuildTarget,
+) -> Result<()> {
+    match analyzer {
+        Analyzer::Native => {
+            let selects_binary = [
+                &build_target.bin,
+                &build_target.bench,
+                &build_target.test,
+                &build_target.example,
+            ]
+            .iter()
+            .any(|t| t.is_some())
+                || build_target.benches
+                || build_target.tests
+                || build_target.examples;
+
+            if build_target.lib && !selects_binary {
+                bail!(
+                    "`--lib` builds only the library, which is not a binary to analyze. Select a \
+                     binary with `--bin`, `--example`, `--test` or `--bench`"
+                )
+            }
+
+            Ok(())
+        }
+        Analyzer::CargoBloat => check_bloat_args(build_target),
+    }
+}
+
+/// Builds the target and returns the size of each crate, using library names
+/// of the crates.
+///
+/// If `opt_level` is [None], the opt-level of the profile is used as-is.
+pub(crate) async fn crate_sizes(
+    analyzer: Analyzer,
+    build_target: &CargoBuildTarget,
+    opt_level: Option<OptLevel>,
+    json_output: Option<&DiagnosticsJson>,
+) -> Result<Vec<BloatCrate>> {
+    check_build_target(analyzer, build_target)?;
+
+    match analyzer {
+        Analyzer::Native => Ok(analyze_build(build_target, opt_level, json_output)
+            .await?
+            .crates()),
+        Analyzer::CargoBloat => {
+            if json_output.is_some() {
+                bail!("`--diagnostics-json` is not supported by `--analyzer cargo-bloat`")
+            }
+
+            ensure_cargo_subcommand("bloat")
+                .await
+                .context("You can install bloat by `cargo install cargo-bloat`")?;
+
+            Ok(run_bloat(build_target, opt_level).await?.crates)
+        }
+    }
+}
+
+/// Builds the target and analyzes the built binary.
+///
+/// Like [run_bloat], a separate target directory is used for each opt-level.
+pub(crate) async fn analyze_build(
+    build_target: &CargoBuildTarget,
+    opt_level: Option<OptLevel>,
+    json_output: Option<&DiagnosticsJson>,
+) -> Result<Analysis> {
+    let Some(opt_level) = opt_level else {
+        return analyze_build_in(build_target, None, vec![], json_output).await;
+    };
+
+    let build_target = build_target.with_target_dir(
+        build_target
+            .target_dir()?
+            .join("ddt-bin-size")
+            .join(format!("opt-level-{}", opt_level)),
+    );
+    let envs = vec![(build_target.profile_env("opt-level"), opt_level.to_string())];
+
+    analyze_build_in(&build_target, None, envs, json_output).await
+}
+
+/// Builds the target in `dir` and analyzes the built binary.
+pub(crate) async fn analyze_build_in(
+    build_target: &CargoBuildTarget,
+    dir: Option<PathBuf>,
+    envs: Vec<(String, String)>,
+    json_output: Option<&DiagnosticsJson>,
+) -> Result<Analysis> {
+    check_build_target(Analyzer::Native, build_target)?;
+
+    let bin = cancellable(async {
+        let bins = compile_with(build_target, dir.as_deref(), &envs, json_output, &[]).await?;
+        select_binary(build_target, bins)
+    })
+    .await?;
+
+    let data = tokio::fs::read(&bin.path)
+        .await
+        .with_context(|| format!("failed to read {}", bin.path.display()))?;
+
+    Analysis::parse(&data).with_context(|| format!("failed to analyze {}", bin.path.display()))
+}
+
+fn select_binary(build_target: &CargoBuildTarget, bins: Vec<BinFile>) -> Result<BinFile> {
+    if bins.len() == 1 {
+        return Ok(bins.into_iter().next().unwrap());
+    }
+
+    if let Some(name) = &build_target.bin {
+        if let Some(bin) = bins.iter().find(|bin| bin.crate_name == *name) {
+            return Ok(bin.clone());
+        }
+    }
+
+    bail!(
+        "the build produced multiple binaries; select one using `--bin`: {}",
+        bins.iter()
+            .map(|bin| bin.crate_name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+#[derive(Debug)]
+pub(crate) struct Analysis {
+    pub file_size: u64,
+    /// Sections stored in the file, in the order of the file.
+    pub sections: Vec<Section>,
+    /// Code symbols, sorted by size in descending order.
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Section {
+    pub name: String,
+    /// Size in the file. Zero for sections like `.bss`.
+    pub size: u64,
+    pub category: SectionCategory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SectionCategory {
+    Code,
+    ReadOnlyData,
+    Data,
+    /// Unwind tables, like `.eh_frame`.
+    Unwind,
+    /// DWARF sections.
+    Debug,
+    /// Symbol tables which are not required at runtime, like `.symtab`.
+    Symbols,
+    /// Everything else, like relocations or dynamic linking information.
+    Metadata,
+}
+
+impl SectionCategory {
+    /// Classifies a section by its name and kind.
+    pub fn of(name: &str, kind: SectionKind) -> Self {
+        let bare = name.trim_start_matches(['.', '_']);
+
+        if matches!(kind, SectionKind::Debug | SectionKind::DebugString)
+            || bare.starts_with("debug")
+            || bare.starts_with("zdebug")
+        {
+            return SectionCategory::Debug;
+        }
+
+        if matches!(
+            bare,
+            "eh_frame"
+                | "eh_frame_hdr"
+                | "gcc_except_table"
+                | "unwind_info"
+                | "ARM.exidx"
+                | "ARM.extab"
+        ) {
+            return SectionCategory::Unwind;
+        }
+
+        // `name` is the name section of wasm.
+        if matches!(name, ".symtab" | ".strtab" | "name") {
+            return SectionCategory::Symbols;
+        }
+
+        match kind {
+            SectionKind::Text => SectionCategory::Code,
+            SectionKind::ReadOnlyData
+            | SectionKind::ReadOnlyDataWithRel
+            | SectionKind::ReadOnlyString => SectionCategory::ReadOnlyData,
+            SectionKind::Data
+            | SectionKind::UninitializedData
+            | SectionKind::Common
+            | SectionKind::Tls
+            | SectionKind::UninitializedTls
+            | SectionKind::TlsVariables => SectionCategory::Data,
+            _ => SectionCategory::Metadata,
+        }
+    }
+}
+
+impl fmt::Display for SectionCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SectionCategory::Code => "code",
+            SectionCategory::ReadOnlyData => "read-only data",
+            SectionCategory::Data => "data",
+            SectionCategory::Unwind => "unwind tables",
+            SectionCategory::Debug => "debug info",
+            SectionCategory::Symbols => "symbol tables",
+            SectionCategory::Metadata => "metadata",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Symbol {
+    /// Demangled name, without the hash.
+    pub name: String,
+    /// Library name of the crate, or [None] if it's not a Rust symbol.
+    pub crate_name: Option<Atom>,
+    pub size: u64,
+}
+
+impl Analysis {
+    /// Parses an ELF, Mach-O, PE or wasm file.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let file = object::File::parse(data).context("failed to parse the binary")?;
+
+        // Text section to its end address.
+        let text_sections = file
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Text)
+            .map(|section| (section.index(), section.address() + section.size()))
+            .collect::<FxHashMap<SectionIndex, u64>>();
+
+        let sections = file
+            .sections()
+            .map(|section| {
+                let name = section.name().unwrap_or("<invalid>").to_string();
+                let category = SectionCategory::of(&name, section.kind());
+
+                Section {
+                    size: section.file_range().map_or(0, |(_, size)| size),
+                    name,
+                    category,
+                }
+            })
+            .collect();
+
+        // (section, address, size, name)
+        let mut raw = file
+            .symbols()
+            .filter(|s| s.kind() == SymbolKind::Text && s.is_definition())
+            .filter_map(|s| {
+                let section = s.section_index()?;
+                text_sections.contains_key(&section).then_some(())?;
+
+                Some((section, s.address(), s.size(), s.name().ok()?))
+            })
+            .collect::<Vec<_>>();
+        raw.sort_by_key(|&(section, address, size, _)| (section.0, address, u64::MAX - size));
+        // Aliases share the code, so only the first one is counted.
+        raw.dedup_by_key(|&mut (section, address, ..)| (section, address));
+
+        let mut symbols = Vec::with_capacity(raw.len());
+        for (i, &(section, address, size, name)) in raw.iter().enumerate() {
+            // Mach-O does not record the size of symbols, so we use the distance to
+            // the next symbol.
+            let size = if size != 0 {
+                size
+            } else {
+                let end = match raw.get(i + 1) {
+                    Some(&(next_section, next_address, ..)) if next_section == section => {
+                        next_address
+                    }
+                    _ => text_sections[&section],
+                };
+                end.saturating_sub(address)
+            };
+
+            let (name, crate_name) = match rustc_demangle::try_demangle(name) {
+                Ok(demangled) => {
+                    let name = format!("{:#}", demangled);
+                    let crate_name = symbol_crate(&name).map(Atom::from);
+                    (name, crate_name)
+                }
+                Err(_) => (name.to_string(), None),
+            };
+
+            symbols.push(Symbol {
+                name,
+                crate_name,
+                size,
+            });
+        }
+        symbols.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(Self {
+            file_size: data.len() as u64,
+            sections,
+            symbols,
+        })
+    }
+
+    /// Returns the size of each crate, sorted by size in descending order.
+    ///
+    /// Symbols which are not from a Rust crate are grouped as `[Unknown]`, like
+    /// `cargo bloat`.
+    pub fn crates(&self) -> Vec<BloatCrate> {
+        let mut sizes = FxHashMap::<Atom, u64>::default();
+        for symbol in &self.symbols {
+            let name = symbol
+                .crate_name
+                .clone()
+                .unwrap_or_else(|| Atom::from(UNKNOWN_CRATE));
+            *sizes.entry(name).or_default() += symbol.size;
+        }
+
+        let mut crates = sizes
+            .into_iter()
+            .map(|(name, size)| BloatCrate { name, size })
+            .collect::<Vec<_>>();
+        crates.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        crates
+    }
+}
+
+/// Returns the library name of the crate which a demangled symbol belongs to.
+///
+/// For trait impls like `<alloc::vec::Vec<T> as core::ops::Drop>::drop`, the
+/// crate of the type is used. If the type is not a path (e.g. `&T`), the crate
+/// of the trait is used instead.
+pub(crate) fn symbol_crate(demangled: &str) -> Option<&str> {
+    let Some(rest) = demangled.strip_prefix('<') else {
+        return first_segment(demangled);
+    };
+
+    let (self_ty, trait_path) = split_as(rest);
+
+    first_segment(strip_type_prefix(self_ty)).or_else(|| trait_path.and_then(first_segment))
+}
+
+/// Splits `T as Trait>::method` into `T` and `Trait>::method`.
+fn split_as(s: &str) -> (&str, Option<&str>) {
+    let mut depth = 0usize;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth == 0 => return (&s[..i], None),
+            '>' => depth -= 1,
+            ' ' if depth == 0 && s[i..].starts_with(" as ") => {
+                return (&s[..i], Some(&s[i + 4..]));
+            }
+            _ => {}
+        }
+    }
+
+    (s, None)
+}
+
+fn strip_type_prefix(mut ty: &str) -> &str {
+    loop {
+        let stripped = ty
+            .trim_start_matches(['&', '*', '[', '('])
+            .trim_start_matches("mut ")
+            .trim_start_matches("const ")
+            .trim_start_matches("dyn ");
+
+        if stripped == ty {
+            return ty;
+        }
+        ty = stripped;
+    }
+}
+
+fn first_segment(path: &str) -> Option<&str> {
+    let (segment, _) = path.split_once("::")?;
+
+    (!segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_'))
+        .then_some(segment)
+}
+
+#[cfg(test)]
+mod test {
+    use object::{write, Architecture, BinaryFormat, Endianness, SymbolFlags, SymbolScope};
+
+    use super::*;
+
+    const SYMBOLS: &[(&str, usize)] = &[
+        ("_ZN5regex5Regex3new17h0123456789abcdefE", 120),
+        ("_ZN5regex5Regex8is_match17h0123456789abcdefE", 40),
+        ("_RNvNtCs1234_6memchr6memchr6memchr", 64),
+        ("memcpy", 16),
+    ];
+
+    fn fixture(format: BinaryFormat) -> Vec<u8> {
+        let mut obj = write::Object::new(format, Architecture::X86_64, Endianness::Little);
+        let text = obj.section_id(write::StandardSection::Text);
+
+        let rodata = obj.section_id(write::StandardSection::ReadOnlyData);
+        obj.append_section_data(rodata, &[1; 32], 1);
+
+        let debug_info =
+            obj.add_section(vec![], b".debug_info".to_vec(), object::SectionKind::Debug);
+        obj.append_section_data(debug_info, &[0; 100], 1);
+
+        for &(name, size) in SYMBOLS {
+            let offset = obj.append_section_data(text, &vec![0x90; size], 1);
+            obj.add_symbol(write::Symbol {
+                name: name.as_bytes().to_vec(),
+                value: offset,
+                size: size as u64,
+                kind: SymbolKind::Text,
+                scope: SymbolScope::Linkage,
+                weak: false,
+                section: write::SymbolSection::Section(text),
+                flags: SymbolFlags::None,
+            });
+        }
+
+        obj.write().unwrap()
+    }
+
+    fn assert_fixture(format: BinaryFormat) {
+        let analysis = Analysis::parse(&fixture(format)).unwrap();
+
+        assert_eq!(analysis.symbols.iter().map(|s| s.size).sum::<u64>(), 240);
+        assert_eq!(analysis.symbols[0].name, "regex::Regex::new");
+        assert_eq!(analysis.symbols[0].size, 120);
+
+        let crates = analysis
+            .crates()
+            .into_iter()
+            .map(|c| (c.name.to_string(), c.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            crates,
+            vec![
+                ("regex".to_string(), 160),
+                ("memchr".to_string(), 64),
+                ("[Unknown]".to_string(), 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn analyze_elf() {
+        assert_fixture(BinaryFormat::Elf);
+    }
+
+    #[test]
+    fn section_categories() {
+        let analysis = Analysis::parse(&fixture(BinaryFormat::Elf)).unwrap();
+
+        let size_of = |category| {
+            analysis
+                .sections
+                .iter()
+                .filter(|s| s.category == category)
+                .map(|s| s.size)
+                .sum::<u64>()
+        };
+        assert_eq!(size_of(SectionCategory::Code), 240);
+        assert_eq!(size_of(SectionCategory::ReadOnlyData), 32);
+        assert_eq!(size_of(SectionCategory::Debug), 100);
+        assert!(size_of(SectionCategory::Symbols) > 0);
+
+        assert_eq!(
+            SectionCategory::of("__eh_frame", SectionKind::ReadOnlyData),
+            SectionCategory::Unwind
+        );
+        assert_eq!(
+            SectionCategory::of("__debug_line", SectionKind::Other),
+            SectionCategory::Debug
+        );
+        assert_eq!(
+            SectionCategory::of("producers", SectionKind::Other),
+            SectionCategory::Metadata
+        );
+    }
+
+    /// Mach-O symbols do not have sizes.
+    #[test]
+    fn analyze_macho() {
+        assert_fixture(BinaryFormat::MachO);
+    }
+
+    #[test]
+    fn crate_of_symbol() {
+        assert_eq!(symbol_crate("regex::Regex::new"), Some("regex"));
+        assert_eq!(
+            symbol_crate("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"),
+            Some("alloc")
+        );
+        assert_eq!(
+            symbol_crate("<&mut T as core::fmt::Debug>::fmt"),
+            Some("core")
+        );
+        assert_eq!(
+            symbol_crate("<[serde_json::Value] as core::fmt::Debug>::fmt"),
+            Some("serde_json")
+        );
+        assert_eq!(symbol_crate("<hstr::Atom>::new"), Some("hstr"));
+        assert_eq!(symbol_crate("memcpy"), None);
+    }
+
+    #[test]
+    fn reject_selections_without_binary() {
+        use clap::Parser;
+
+        let check = |analyzer, args: &[&str]| {
+            let build_target =
+                CargoBuildTarget::parse_from(std::iter::once("ddt").chain(args.iter().copied()));
+            check_build_target(analyzer, &build_target)
+        };
+
+        assert!(check(Analyzer::Native, &[]).is_ok());
+        assert!(check(Analyzer::Native, &["--lib"]).is_err());
+        assert!(check(Analyzer::Native, &["--lib", "--bin", "app"]).is_ok());
+        assert!(check(Analyzer::Native, &["--tests"]).is_ok());
+
+        assert!(check(Analyzer::CargoBloat, &["--lib"]).is_ok());
+        assert!(check(Analyzer::CargoBloat, &["--test", "parse"]).is_ok());
+        assert!(check(Analyzer::CargoBloat, &["--tests"]).is_err());
+        assert!(check(Analyzer::CargoBloat, &["--bench", "parse"]).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Measures the runtime of candidate build configurations.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use toml_edit::DocumentMut;
+use tracing::warn;
+
+use crate::util::{cargo_build::cargo_target_dir, PrettyCmd};
+
+/// Options to benchmark each candidate configuration.
+#[derive(Debug, Args)]
+pub(crate) struct BenchArgs {
+    /// Shell command used to benchmark each candidate configuration, e.g.
+    /// `cargo bench --bench parse`.
+    ///
+    /// If the command prints the results of libtest or criterion benchmarks,
+    /// the sum of the reported times is used, so the time spent in cargo is
+    /// not counted. Otherwise the runtime of the whole command is used, so it
+    /// should run a built binary, like `$CARGO_TARGET_DIR/release/app input`,
+    /// which is built by `--bench-build-cmd`.
+    ///
+    /// The command is run once to warm up, and then `--bench-runs` times. The
+    /// fastest run is used.
+    #[clap(long)]
+    pub bench_cmd: Option<String>,
+
+    /// Shell command which builds the benchmark of each candidate
+    /// configuration, e.g. `cargo build --release`. It's not timed.
+    ///
+    /// `CARGO_TARGET_DIR` is set to the target directory of the configuration
+    /// for both commands.
+    #[clap(long, requires = "bench_cmd")]
+    bench_build_cmd: Option<String>,
+
+    /// Number of timed runs of `--bench-cmd`, after the warm-up run.
+    #[clap(long, default_value = "3", requires = "bench_cmd")]
+    bench_runs: usize,
+}
+
+impl BenchArgs {
+    /// Writes `manifest` to `manifest_path` and measures the runtime of the
+    /// benchmark command.
+    pub async fn measure(
+        &self,
+        manifest_path: &Path,
+        manifest: &DocumentMut,
+        description: &str,
+    ) -> Result<Option<Duration>> {
+        if self.bench_cmd.is_none() {
+            return Ok(None);
+        }
+
+        std::fs::write(manifest_path, manifest.to_string())
+            .context("failed to write the root cargo.toml")?;
+
+        let target_dir = cargo_target_dir()?.join("ddt-bin-size").join("bench");
+
+        self.measure_with(
+            description,
+            &[(
+                "CARGO_TARGET_DIR".into(),
+                target_dir.to_string_lossy().to_string(),
+            )],
+        )
+        .await
+    }
+
+    /// Measures the runtime of the benchmark command, with extra environment
+    /// variables.
+    pub async fn measure_with(
+        &self,
+        description: &str,
+        envs: &[(String, String)],
+    ) -> Result<Option<Duration>> {
+        let Some(bench_cmd) = &self.bench_cmd else {
+            return Ok(None);
+        };
+
+        let shell_cmd = |description: String, command: &str| {
+            let mut cmd = PrettyCmd::new(description, shell());
+            cmd.arg(shell_flag()).arg(command);
+            for (k, v) in envs {
+                cmd.env(k, v);
+            }
+            cmd
+        };
+
+        if let Some(build_cmd) = &self.bench_build_cmd {
+            shell_cmd(
+                format!("Building the benchmark of {}", description),
+                build_cmd,
+            )
+            .exec()
+            .await
+            .with_context(|| format!("failed to build the benchmark of {}", description))?;
+        }
+
+        let mut fastest = None;
+        // The first run is a warm-up, which also builds `cargo bench`.
+        for run in 0..=self.bench_runs.max(1) {
+            let start = Instant::now();
+
+            let output = shell_cmd(
+                format!("Benchmarking {} (run {})", description, run),
+                bench_cmd,
+            )
+            .output()
+            .await
+            .with_context(|| format!("failed to benchmark {}", description))?;
+
+            let elapsed = start.elapsed();
+
+            if run == 0 {
+                continue;
+            }
+
+            let runtime = reported_time(&output).unwrap_or(elapsed);
+            if fastest.map_or(true, |fastest| runtime < fastest) {
+                fastest = Some(runtime);
+            }
+        }
+
+        Ok(fastest)
+    }
+}
+
+/// Sums the times reported by libtest or criterion benchmarks in `output`.
+///
+/// Returns [None] if there are no results.
+fn reported_time(output: &str) -> Option<Duration> {
+    output
+        .lines()
+        .filter_map(|line| libtest_time(line).or_else(|| criterion_time(line)))
+        .reduce(|a, b| a + b)
+}
+
+/// Parses `test parse ... bench:       1,234.50 ns/iter (+/- 56.70)`.
+fn libtest_time(line: &str) -> Option<Duration> {
+    let (_, rest) = line.split_once(" bench:")?;
+    let (ns, _) = rest.split_once("ns/iter")?;
+    let ns = ns.trim().replace(',', "").parse::<f64>().ok()?;
+
+    Some(Duration::from_secs_f64(ns / 1e9))
+}
+
+/// Parses `parse  time:   [1.2000 ms 1.2500 ms 1.3000 ms]`, where the middle
+/// value is the estimate.
+fn criterion_time(line: &str) -> Option<Duration> {
+    let (_, rest) = line.split_once("time:")?;
+    let (values, _) = rest.trim().strip_prefix('[')?.split_once(']')?;
+
+    let [_, _, value, unit, _, _] = values.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let scale = match unit {
+        "ps" => 1e-12,
+        "ns" => 1e-9,
+        "µs" | "us" => 1e-6,
+        "ms" => 1e-3,
+        "s" => 1.0,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs_f64(value.parse::<f64>().ok()? * scale))
+}
+
+/// Restores the root `Cargo.toml` when dropped, as benchmarking overwrites it.
+pub(super) struct RestoreManifest {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+impl Drop for RestoreManifest {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::write(&self.path, &self.content) {
+            warn!("Failed to restore {}: {:?}", self.path.display(), err);
+        }
+    }
+}
+
+/// Returns the change of `runtime` compared to `baseline`, in percent.
+pub(super) fn slowdown_percent(runtime: Duration, baseline: Duration) -> f64 {
+    if baseline.is_zero() {
+        return 0.0;
+    }
+
+    (runtime.as_secs_f64() / baseline.as_secs_f64() - 1.0) * 100.0
+}
+
+pub(super) fn format_runtime(runtime: Option<Duration>, baseline: Option<Duration>) -> String {
+    let Some(runtime) = runtime else {
+        return String::new();
+    };
+
+    match baseline {
+        Some(baseline) if baseline != runtime => format!(
+            ", {:.3}s ({:+.1}%)",
+            runtime.as_secs_f64(),
+            slowdown_percent(runtime, baseline)
+        ),
+        _ => format!(", {:.3}s", runtime.as_secs_f64()),
+    }
+}
+
+fn shell() -> &'static str {
+    if cfg!(windows) {
+        "cmd"
+    } else {
+        "sh"
+    }
+}
+
+fn shell_flag() -> &'static str {
+    if cfg!(windows) {
+        "/C"
+    } else {
+        "-c"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_reported_times() {
+        let libtest = "
+running 2 tests
+test parse_large ... bench:       1,500.00 ns/iter (+/- 20.00)
+test parse_small ... bench:         500 ns/iter (+/- 3)
+
+test result: ok. 0 passed; 0 failed; 0 ignored; 2 measured; 0 filtered out
+";
+        assert_eq!(reported_time(libtest), Some(Duration::from_nanos(2000)));
+
+        let criterion = "
+parse/large             time:   [1.2000 ms 1.2500 ms 1.3000 ms]
+                        change: [-1.0% +0.5% +2.0%] (p = 0.50 > 0.05)
+                        No change in performance detected.
+parse/a-very-long-benchmark-name
+                        time:   [740.00 µs 750.00 µs 760.00 µs]
+";
+        assert_eq!(reported_time(criterion), Some(Duration::from_millis(2)));
+
+        assert_eq!(reported_time("Finished in 1.5s\n"), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Wrapper for `cargo bloat`.
+
+use anyhow::{bail, Context, Result};
+use hstr::Atom;
+use serde::Deserialize;
+
+use super::OptLevel;
+use crate::util::{cargo_build::CargoBuildTarget, PrettyCmd};
+
+/// Rejects the options which `cargo bloat` does not support, so it's reported
+/// before anything is built.
+pub(crate) fn check_bloat_args(build_target: &CargoBuildTarget) -> Result<()> {
+    for (flag, used) in [
+        ("--benches", build_target.benches),
+        ("--bench", build_target.bench.is_some()),
+        ("--tests", build_target.tests),
+        ("--examples", build_target.examples),
+        ("--workspace", build_target.workspace),
+        ("--manifest-path", build_target.manifest_path.is_some()),
+        ("--offline", build_target.offline),
+    ] {
+        if used {
+            bail!(
+                "`{}` is not supported by cargo bloat, which analyzes a single binary selected \
+                 by `--bin`, `--example`, `--test` or `--lib`. Use `--analyzer native` instead",
+                flag
+            )
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `cargo bloat --crates`.
+///
+/// If `opt_level` is [None], the opt-level of the profile is used as-is.
+/// Otherwise, a separate target directory is used for each opt-level, so the
+/// builds can run concurrently and the build cache is not invalidated.
+pub(crate) async fn run_bloat(
+    build_target: &CargoBuildTarget,
+    opt_level: Option<OptLevel>,
+) -> Result<BloatOutput> {
+    check_bloat_args(build_target)?;
+
+    let mut cmd = PrettyCmd::new(
+        match opt_level {
+            Some(opt_level) => format!("Running cargo bloat with opt-level = {}", opt_level),
+            None => "Running cargo bloat".into(),
+        },
+        "cargo",
+    );
+    cmd.arg("bloat");
+
+    cmd.arg("--crates");
+    // Show all crates
+    cmd.arg("-n").arg("0");
+
+    // Ouptut in json format.
+    cmd.arg("--message-format").arg("json");
+
+    cmd.env(build_target.profile_env("debug"), "1");
+    let build_target = match opt_level {
+        Some(opt_level) => {
+            cmd.env(build_target.profile_env("opt-level"), opt_level.to_string());
+
+            build_target.with_target_dir(
+                build_target
+                    .target_dir()?
+                    .join("ddt-bin-size")
+                    .join(format!("opt-level-{}", opt_level)),
+            )
+        }
+        None => build_target.clone(),
+    };
+
+    cmd.args(build_target.cargo_args());
+
+    let output = cmd.output().await.context("failed to run cargo bloat")?;
+
+    let output: BloatOutput =
+        serde_json::from_str(&output).context("failed to parse bloat output")?;
+
+    Ok(output)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BloatOutput {
+    // file_size: u64,
+    // text_section_size: u64,
+    pub crates: Vec<BloatCrate>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BloatCrate {
+    pub name: Atom,
+    /// File size in bytes.
+    pub size: u64,
+}
@@ -0,0 +1,352 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use humansize::{format_size, DECIMAL};
+use rustc_hash::FxHashMap;
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+use toml_edit::{value, DocumentMut};
+
+use super::{
+    analyze::{analyze_build, check_build_target, Analyzer},
+    parse_size,
+};
+use crate::{
+    cli::util::cargo::to_original_crate_name,
+    util::{
+        cargo_build::{CargoBuildTarget, DiagnosticsArgs},
+        config::{config_path, load_config_section, CONFIG_FILE_NAME},
+        wrap,
+    },
+};
+
+/// Check the size budgets in `ddt.toml`.
+///
+/// Each budget in `[bin-size.budgets.<name>]` selects the target to build using
+/// the same keys as the command line options, like `bin` or `release`, and
+/// limits the `total` size of the artifact and the size of each crate in
+/// `crates`.
+#[derive(Debug, Args)]
+pub(super) struct CheckCommand {
+    /// Names of the budgets to check. All budgets are checked by default.
+    #[clap(long = "budget")]
+    budgets: Vec<String>,
+
+    /// Rewrite the checked budgets to the current sizes plus the margin.
+    #[clap(long)]
+    update: bool,
+
+    /// Headroom added to the measured sizes by `--update`, in percent.
+    #[clap(long, default_value_t = 10.0, requires = "update")]
+    margin_percent: f64,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+/// `[bin-size]` in `ddt.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BinSizeConfig {
+    #[serde(default)]
+    budgets: BTreeMap<String, Budget>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Budget {
+    total: Option<Size>,
+
+    /// Package name to the budget.
+    #[serde(default)]
+    crates: BTreeMap<String, Size>,
+
+    #[serde(flatten)]
+    build_target: CargoBuildTarget,
+
+    /// The keys which are not used by `build_target`, like misspelled keys.
+    /// `deny_unknown_fields` does not work with `flatten`.
+    #[serde(flatten)]
+    unknown_keys: BTreeMap<String, IgnoredAny>,
+}
+
+impl BinSizeConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, budget) in &self.budgets {
+            if !budget.unknown_keys.is_empty() {
+                bail!(
+                    "unknown keys in `[bin-size.budgets.{}]`: {}",
+                    name,
+                    budget
+                        .unknown_keys
+                        .keys()
+                        .map(|key| format!("`{}`", key))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of bytes, or a string like `10KB`.
+#[derive(Debug, Clone, Copy)]
+struct Size(u64);
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(v) => Ok(Size(v)),
+            Raw::Text(s) => parse_size(&s)
+                .map(Size)
+                .map_err(|err| serde::de::Error::custom(format!("{:#}", err))),
+        }
+    }
+}
+
+struct Measured {
+    total: u64,
+    /// Package name to the size.
+    crates: FxHashMap<String, u64>,
+}
+
+struct Row<'a> {
+    budget: &'a str,
+    item: String,
+    size: u64,
+    limit: u64,
+}
+
+impl fmt::Display for Row<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<30} {:>12} / {:>12} ({:.1}%)",
+            if self.size > self.limit { "✗" } else { "✓" },
+            format!("{}: {}", self.budget, self.item),
+            format_size(self.size, DECIMAL),
+            format_size(self.limit, DECIMAL),
+            if self.limit == 0 {
+                0.0
+            } else {
+                self.size as f64 * 100.0 / self.limit as f64
+            }
+        )
+    }
+}
+
+impl CheckCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            let config: BinSizeConfig = load_config_section("bin-size")?;
+            config
+                .validate()
+                .with_context(|| format!("invalid `[bin-size]` in {}", CONFIG_FILE_NAME))?;
+
+            if config.budgets.is_empty() {
+                bail!(
+                    "no budget is configured in `[bin-size.budgets.<name>]` of {}",
+                    CONFIG_FILE_NAME
+                )
+            }
+
+            for name in &self.budgets {
+                if !config.budgets.contains_key(name) {
+                    bail!("unknown budget `{}`", name)
+                }
+            }
+
+            let budgets = config
+                .budgets
+                .iter()
+                .filter(|(name, _)| self.budgets.is_empty() || self.budgets.contains(name))
+                .collect::<Vec<_>>();
+
+            for (name, budget) in &budgets {
+                check_build_target(Analyzer::Native, &budget.build_target)
+                    .with_context(|| format!("invalid budget `{}`", name))?;
+            }
+
+            let json_output = self.diagnostics.open().await?;
+            let mut measured = vec![];
+            for (name, budget) in &budgets {
+                let analysis = analyze_build(&budget.build_target, None, json_output.as_ref())
+                    .await
+                    .with_context(|| format!("failed to measure the budget `{}`", name))?;
+
+                let mut crates = FxHashMap::<String, u64>::default();
+                for crate_ in analysis.crates() {
+                    let name = to_original_crate_name(crate_.name.clone()).unwrap_or(crate_.name);
+                    *crates.entry(name.to_string()).or_default() += crate_.size;
+                }
+
+                measured.push(Measured {
+                    total: analysis.file_size,
+                    crates,
+                });
+            }
+
+            if self.update {
+                return self.update_budgets(&budgets, &measured);
+            }
+
+            let mut exceeded = 0;
+            for ((name, budget), measured) in budgets.iter().zip(&measured) {
+                let mut rows = vec![];
+
+                if let Some(total) = budget.total {
+                    rows.push(Row {
+                        budget: name,
+                        item: "total".into(),
+                        size: measured.total,
+                        limit: total.0,
+                    });
+                }
+
+                for (crate_name, limit) in &budget.crates {
+                    rows.push(Row {
+                        budget: name,
+                        item: crate_name.clone(),
+                        size: measured.crates.get(crate_name).copied().unwrap_or(0),
+                        limit: limit.0,
+                    });
+                }
+
+                for row in rows {
+                    if row.size > row.limit {
+                        exceeded += 1;
+                    }
+                    println!("{}", row);
+                }
+            }
+
+            if exceeded > 0 {
+                bail!("{} size budgets are exceeded", exceeded)
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to check the size budgets")
+    }
+
+    fn update_budgets(&self, budgets: &[(&String, &Budget)], measured: &[Measured]) -> Result<()> {
+        let path = config_path()?;
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut doc = content
+            .parse::<DocumentMut>()
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        let with_margin = |size: u64| {
+            let size = (size as f64 * (1.0 + self.margin_percent / 100.0)).ceil() as u64;
+            // Round up to kilobytes for readability.
+            format!("{}KB", size.div_ceil(1000))
+        };
+
+        for ((name, budget), measured) in budgets.iter().zip(measured) {
+            let table = &mut doc["bin-size"]["budgets"][name.as_str()];
+
+            let total = with_margin(measured.total);
+            println!("{}: total = {}", name, total);
+            table["total"] = value(total);
+
+            for crate_name in budget.crates.keys() {
+                let size = with_margin(measured.crates.get(crate_name).copied().unwrap_or(0));
+                println!("{}: {} = {}", name, crate_name, size);
+                table["crates"][crate_name.as_str()] = value(size);
+            }
+        }
+
+        std::fs::write(&path, doc.to_string())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Result<BinSizeConfig> {
+        let config: BinSizeConfig = toml_edit::de::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("10KB").unwrap(), 10_000);
+        assert_eq!(parse_size(" 10 kb ").unwrap(), 10_000);
+        assert_eq!(parse_size("1.5MiB").unwrap(), 1_572_864);
+        assert_eq!(parse_size("2mb").unwrap(), 2_000_000);
+        assert_eq!(parse_size("3B").unwrap(), 3);
+        assert!(parse_size("10GB").is_err());
+        assert!(parse_size("KB").is_err());
+        assert!(parse_size("").is_err());
+
+        let config = parse(
+            r#"
+            [budgets.cli]
+            total = 1500000
+            crates = { regex = "300KB", std = "1.5MiB" }
+            "#,
+        )
+        .unwrap();
+        let cli = &config.budgets["cli"];
+        assert_eq!(cli.total.unwrap().0, 1_500_000);
+        assert_eq!(cli.crates["regex"].0, 300_000);
+        assert_eq!(cli.crates["std"].0, 1_572_864);
+
+        let err = parse("[budgets.cli]\ntotal = \"10 parsecs\"").unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown size unit `parsecs`"));
+    }
+
+    #[test]
+    fn budget_keys() {
+        let config = parse(
+            r#"
+            [budgets.cli]
+            bin = "cli"
+            release = true
+            package = ["app"]
+            unstable = ["build-std"]
+            total = "1MB"
+            "#,
+        )
+        .unwrap();
+        let cli = &config.budgets["cli"];
+        assert_eq!(cli.build_target.bin.as_deref(), Some("cli"));
+        assert!(cli.build_target.release);
+        assert_eq!(cli.build_target.packages, ["app"]);
+        assert_eq!(cli.build_target.unstable_flags, ["build-std"]);
+
+        let err = parse(
+            r#"
+            [budgets.cli]
+            bin = "cli"
+            relase = true
+            totl = "1MB"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown keys in `[bin-size.budgets.cli]`: `relase`, `totl`"
+        );
+    }
+}
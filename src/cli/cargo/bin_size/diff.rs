@@ -0,0 +1,466 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use humansize::{format_size, DECIMAL};
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+use super::analyze::{analyze_build_in, check_build_target, Analysis, Analyzer};
+use crate::{
+    cli::util::cargo::to_original_crate_name,
+    util::{
+        cargo_build::{CargoBuildTarget, DiagnosticsArgs, DiagnosticsJson},
+        wrap, PrettyCmd,
+    },
+};
+
+/// Compare the size of each crate and symbol between two git revisions.
+///
+/// Each revision is built in a temporary git worktree, so the working tree is
+/// not touched.
+#[derive(Debug, Args)]
+pub(super) struct DiffCommand {
+    /// The old revision.
+    old: String,
+
+    /// The new revision. Defaults to the working tree.
+    new: Option<String>,
+
+    #[clap(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    /// Maximum number of symbols to print in the text output.
+    #[clap(long, default_value_t = 30)]
+    max_symbols: usize,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    old: Revision,
+    new: Revision,
+    /// Sorted by the absolute delta in descending order.
+    crates: Vec<Delta>,
+    /// Sorted by the absolute delta in descending order.
+    symbols: Vec<Delta>,
+}
+
+#[derive(Debug, Serialize)]
+struct Revision {
+    name: String,
+    /// The size of the binary file, including the data and debug info.
+    total_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Delta {
+    name: String,
+    old_size: u64,
+    new_size: u64,
+    delta: i64,
+}
+
+impl DiffCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            check_build_target(Analyzer::Native, &self.build_target)?;
+            if let OutputFormat::Json = self.format {
+                self.diagnostics.ensure_not_stdout()?;
+            }
+
+            // Cargo hashes path packages relative to the workspace root, so
+            // sharing a target directory between worktrees would make cargo reuse
+            // stale artifacts. It's made absolute, as the builds run in the
+            // worktrees.
+            let target_dir = std::env::current_dir()
+                .context("failed to get the current directory")?
+                .join(self.build_target.target_dir()?)
+                .join("ddt-bin-size")
+                .join("diff");
+
+            let json_output = self.diagnostics.open().await?;
+            let old = self
+                .analyze_revision(
+                    Some(&self.old),
+                    &target_dir.join("old"),
+                    json_output.as_ref(),
+                )
+                .await?;
+            let new = self
+                .analyze_revision(
+                    self.new.as_deref(),
+                    &target_dir.join("new"),
+                    json_output.as_ref(),
+                )
+                .await?;
+
+            let report = Report {
+                old: Revision {
+                    name: self.old.clone(),
+                    total_size: old.file_size,
+                },
+                new: Revision {
+                    name: self.new.clone().unwrap_or_else(|| "working tree".into()),
+                    total_size: new.file_size,
+                },
+                crates: diff(crate_sizes(&old), crate_sizes(&new)),
+                symbols: diff(symbol_sizes(&old), symbol_sizes(&new)),
+            };
+
+            match self.format {
+                OutputFormat::Text => print!("{}", render_text(&report, self.max_symbols)),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report)
+                        .context("failed to serialize the report")?
+                ),
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to compare the binary size")
+    }
+
+    /// Builds `rev`, or the working tree if `rev` is [None].
+    async fn analyze_revision(
+        &self,
+        rev: Option<&str>,
+        target_dir: &Path,
+        json_output: Option<&DiagnosticsJson>,
+    ) -> Result<Analysis> {
+        let build_target = self.build_target.with_target_dir(target_dir.to_path_buf());
+
+        let Some(rev) = rev else {
+            return analyze_build_in(&build_target, None, vec![], json_output).await;
+        };
+
+        // The repository of the manifest, which may not be the one of the
+        // current directory.
+        let repo_dir = match self
+            .build_target
+            .manifest_path
+            .as_deref()
+            .and_then(Path::parent)
+        {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let worktree = Worktree::new(rev, repo_dir).await?;
+
+        let analysis = async {
+            let mut build_target = build_target;
+            // Otherwise the manifest in the working tree would be built.
+            if let Some(path) = &mut build_target.manifest_path {
+                *path = worktree.path_of(path)?;
+            }
+
+            analyze_build_in(
+                &build_target,
+                Some(worktree.cwd.clone()),
+                vec![],
+                json_output,
+            )
+            .await
+        }
+        .await
+        .with_context(|| format!("failed to analyze the binary at `{}`", rev));
+
+        worktree.remove().await;
+
+        analysis
+    }
+}
+
+/// A temporary git worktree.
+///
+/// It should be removed with [Worktree::remove]. Dropping it removes it too,
+/// but blocks the runtime, so that's only for errors and Ctrl-C.
+struct Worktree {
+    dir: TempDir,
+    /// The root of the main working tree.
+    toplevel: PathBuf,
+    /// The current directory, relative to the repository root, in the
+    /// worktree.
+    cwd: PathBuf,
+    removed: bool,
+}
+
+impl Worktree {
+    /// Checks out `rev` of the repository which contains `repo_dir`.
+    async fn new(rev: &str, repo_dir: &Path) -> Result<Self> {
+        let toplevel = PrettyCmd::new("Finding the git repository", "git")
+            .dir(repo_dir)
+            .arg("rev-parse")
+            .arg("--show-toplevel")
+            .output()
+            .await?;
+        let toplevel = PathBuf::from(toplevel.trim());
+
+        let current_dir = std::env::current_dir()?;
+        let relative = current_dir
+            .strip_prefix(&toplevel)
+            .unwrap_or_else(|_| Path::new(""));
+
+        let dir = TempDir::new().context("failed to create a directory for the worktree")?;
+
+        PrettyCmd::new(format!("Checking out `{}`", rev), "git")
+            .dir(&toplevel)
+            .arg("worktree")
+            .arg("add")
+            .arg("--detach")
+            .arg(dir.path())
+            .arg(rev)
+            .exec()
+            .await
+            .with_context(|| format!("failed to create a worktree for `{}`", rev))?;
+        info!("Checked out `{}` to {}", rev, dir.path().display());
+
+        Ok(Self {
+            cwd: dir.path().join(relative),
+            dir,
+            toplevel,
+            removed: false,
+        })
+    }
+
+    /// Maps a path in the main working tree to the worktree.
+    fn path_of(&self, path: &Path) -> Result<PathBuf> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {}", path.display()))?;
+        let relative = path.strip_prefix(&self.toplevel).with_context(|| {
+            format!(
+                "{} is not in the git repository at {}",
+                path.display(),
+                self.toplevel.display()
+            )
+        })?;
+
+        Ok(self.dir.path().join(relative))
+    }
+
+    async fn remove(mut self) {
+        let res = PrettyCmd::new("Removing the worktree", "git")
+            .dir(&self.toplevel)
+            .arg("worktree")
+            .arg("remove")
+            .arg("--force")
+            .arg(self.dir.path())
+            .exec()
+            .await;
+
+        match res {
+            Ok(()) => self.removed = true,
+            Err(err) => warn!(
+                "Failed to remove the worktree at {}: {:?}",
+                self.dir.path().display(),
+                err
+            ),
+        }
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+
+        let status = std::process::Command::new("git")
+            .current_dir(&self.toplevel)
+            .arg("worktree")
+            .arg("remove")
+            .arg("--force")
+            .arg(self.dir.path())
+            .status();
+
+        if !status.is_ok_and(|s| s.success()) {
+            warn!(
+                "Failed to remove the worktree at {}",
+                self.dir.path().display()
+            );
+        }
+    }
+}
+
+fn crate_sizes(analysis: &Analysis) -> FxHashMap<String, u64> {
+    let mut sizes = FxHashMap::default();
+
+    for crate_ in analysis.crates() {
+        let name = to_original_crate_name(crate_.name.clone()).unwrap_or(crate_.name);
+        *sizes.entry(name.to_string()).or_default() += crate_.size;
+    }
+
+    sizes
+}
+
+fn symbol_sizes(analysis: &Analysis) -> FxHashMap<String, u64> {
+    let mut sizes = FxHashMap::default();
+
+    // Generic functions may have multiple instances with the same name.
+    for symbol in &analysis.symbols {
+        *sizes.entry(symbol.name.clone()).or_default() += symbol.size;
+    }
+
+    sizes
+}
+
+/// Returns the changed entries, sorted by the absolute delta in descending
+/// order.
+fn diff(old: FxHashMap<String, u64>, mut new: FxHashMap<String, u64>) -> Vec<Delta> {
+    let mut deltas = vec![];
+
+    for (name, old_size) in old {
+        let new_size = new.remove(&name).unwrap_or(0);
+        deltas.push(Delta {
+            name,
+            old_size,
+            new_size,
+            delta: new_size as i64 - old_size as i64,
+        });
+    }
+
+    for (name, new_size) in new {
+        deltas.push(Delta {
+            name,
+            old_size: 0,
+            new_size,
+            delta: new_size as i64,
+        });
+    }
+
+    deltas.retain(|d| d.delta != 0);
+    deltas.sort_by(|a, b| {
+        b.delta
+            .unsigned_abs()
+            .cmp(&a.delta.unsigned_abs())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    deltas
+}
+
+fn format_delta(delta: i64) -> String {
+    format!(
+        "{}{}",
+        if delta < 0 { "-" } else { "+" },
+        format_size(delta.unsigned_abs(), DECIMAL)
+    )
+}
+
+fn render_rows(s: &mut String, rows: &[Delta]) {
+    let _ = writeln!(s, "  {:>12} {:>12} {:>12}  name", "delta", "old", "new");
+
+    for row in rows {
+        let _ = writeln!(
+            s,
+            "  {:>12} {:>12} {:>12}  {}",
+            format_delta(row.delta),
+            format_size(row.old_size, DECIMAL),
+            format_size(row.new_size, DECIMAL),
+            row.name
+        );
+    }
+}
+
+fn render_text(report: &Report, max_symbols: usize) -> String {
+    let mut s = String::new();
+
+    let _ = writeln!(s, "Crates:");
+    if report.crates.is_empty() {
+        let _ = writeln!(s, "  (no changes)");
+    } else {
+        render_rows(&mut s, &report.crates);
+    }
+
+    let _ = writeln!(s, "\nSymbols:");
+    if report.symbols.is_empty() {
+        let _ = writeln!(s, "  (no changes)");
+    } else {
+        render_rows(
+            &mut s,
+            &report.symbols[..report.symbols.len().min(max_symbols)],
+        );
+
+        if report.symbols.len() > max_symbols {
+            let _ = writeln!(
+                s,
+                "  ... and {} more symbols",
+                report.symbols.len() - max_symbols
+            );
+        }
+    }
+
+    let _ = writeln!(
+        s,
+        "\nTotal: {} ({}) -> {} ({}): {}",
+        format_size(report.old.total_size, DECIMAL),
+        report.old.name,
+        format_size(report.new.total_size, DECIMAL),
+        report.new.name,
+        format_delta(report.new.total_size as i64 - report.old.total_size as i64)
+    );
+
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sizes(sizes: &[(&str, u64)]) -> FxHashMap<String, u64> {
+        sizes
+            .iter()
+            .map(|&(name, size)| (name.to_string(), size))
+            .collect()
+    }
+
+    #[test]
+    fn diff_sizes() {
+        let deltas = diff(
+            sizes(&[("std", 1000), ("regex", 500), ("log", 20), ("serde", 300)]),
+            sizes(&[
+                ("std", 1000),
+                ("regex", 400),
+                ("serde", 400),
+                ("memchr", 150),
+            ]),
+        );
+
+        assert_eq!(
+            deltas
+                .iter()
+                .map(|d| (&*d.name, d.old_size, d.new_size, d.delta))
+                .collect::<Vec<_>>(),
+            [
+                ("memchr", 0, 150, 150),
+                // Ties are sorted by name.
+                ("regex", 500, 400, -100),
+                ("serde", 300, 400, 100),
+                ("log", 20, 0, -20),
+            ]
+        );
+
+        assert_eq!(format_delta(-100), "-100 B");
+        assert_eq!(format_delta(1500), "+1.50 kB");
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs::File,
+    io::{BufWriter, Cursor},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::Serialize;
+use tracing::info;
+
+use super::analyze::{analyze_build, Analysis};
+use crate::{
+    cli::util::{cargo::to_original_crate_name, open_file},
+    util::{
+        cargo_build::{CargoBuildTarget, DiagnosticsArgs},
+        wrap,
+    },
+};
+
+/// Render the size of each symbol as a flamegraph, grouped by crate and
+/// module.
+#[derive(Debug, Args)]
+pub(super) struct GraphCommand {
+    /// The path to the output flamegraph file
+    #[clap(long, short = 'o')]
+    output_path: Option<PathBuf>,
+
+    /// Also render a treemap as a self-contained HTML file, next to the
+    /// flamegraph.
+    #[clap(long)]
+    treemap: bool,
+
+    /// Write the collapsed stack lines (`crate;module;function size`) to a
+    /// file.
+    #[clap(long)]
+    collapsed: Option<PathBuf>,
+
+    /// Ignore symbols smaller than this number of bytes.
+    #[clap(long, default_value_t = 0)]
+    min_size: u64,
+
+    #[clap(long)]
+    no_open: bool,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+impl GraphCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            let json_output = self.diagnostics.open().await?;
+            let analysis = analyze_build(&self.build_target, None, json_output.as_ref()).await?;
+
+            let collapsed = collapse(&analysis, self.min_size);
+
+            if let Some(path) = &self.collapsed {
+                std::fs::write(path, &collapsed)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+
+            let output_path = match &self.output_path {
+                Some(path) => path.clone(),
+                None => {
+                    let dir = self.build_target.output_dir("ddt-bin-size")?.join("graph");
+                    std::fs::create_dir_all(&dir)
+                        .with_context(|| format!("failed to create {}", dir.display()))?;
+
+                    dir.join(format!(
+                        "{}.svg",
+                        chrono::Local::now().format("%F_%H%M%S-%3f")
+                    ))
+                }
+            };
+
+            write_flamegraph(&collapsed, &output_path)?;
+            info!("Flamegraph printed to {}", output_path.display());
+
+            if self.treemap {
+                let treemap_path = output_path.with_extension("html");
+                std::fs::write(&treemap_path, render_treemap(&collapsed)?)
+                    .with_context(|| format!("failed to write {}", treemap_path.display()))?;
+                info!("Treemap printed to {}", treemap_path.display());
+
+                if !self.no_open {
+                    let _ = open_file(&treemap_path);
+                }
+            }
+
+            if !self.no_open {
+                let _ = open_file(&output_path);
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to render the size graph")
+    }
+}
+
+/// Converts the symbols into collapsed stack lines.
+fn collapse(analysis: &Analysis, min_size: u64) -> String {
+    let mut s = String::new();
+
+    for symbol in &analysis.symbols {
+        if symbol.size == 0 || symbol.size < min_size {
+            continue;
+        }
+
+        let crate_name = match &symbol.crate_name {
+            Some(name) => to_original_crate_name(name.clone())
+                .unwrap_or_else(|_| name.clone())
+                .to_string(),
+            None => "[Unknown]".to_string(),
+        };
+
+        let _ = writeln!(
+            s,
+            "{} {}",
+            symbol_frames(&crate_name, &symbol.name).join(";"),
+            symbol.size
+        );
+    }
+
+    s
+}
+
+/// Splits a demangled symbol into frames, starting with the crate name.
+fn symbol_frames(crate_name: &str, name: &str) -> Vec<String> {
+    let mut segments = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (i, c) in name.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth = depth.saturating_sub(1),
+            ':' if depth == 0 && name[i..].starts_with("::") && i >= start => {
+                segments.push(&name[start..i]);
+                start = i + 2;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&name[start..]);
+
+    // The crate name is already the first frame.
+    if segments.first() == Some(&&*crate_name.replace('-', "_")) {
+        segments.remove(0);
+    }
+
+    let mut frames = vec![crate_name.to_string()];
+    frames.extend(
+        segments
+            .into_iter()
+            .filter(|segment| !segment.is_empty())
+            // `;` separates frames in the collapsed format.
+            .map(|segment| segment.replace(';', ":")),
+    );
+
+    frames
+}
+
+fn write_flamegraph(collapsed: &str, output_path: &Path) -> Result<()> {
+    let file = File::create(output_path)
+        .with_context(|| format!("failed to create {}", output_path.display()))?;
+
+    let mut options = inferno::flamegraph::Options::default();
+    options.title = "Binary size".into();
+    options.count_name = "bytes".into();
+
+    inferno::flamegraph::from_reader(
+        &mut options,
+        Cursor::new(collapsed.as_bytes()),
+        BufWriter::new(file),
+    )
+    .with_context(|| {
+        format!(
+            "unable to generate a flamegraph file ({}) from the symbol sizes",
+            output_path.display()
+        )
+    })
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Node {
+    name: String,
+    size: u64,
+    #[serde(skip)]
+    children_map: BTreeMap<String, Node>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn insert(&mut self, frames: &[&str], size: u64) {
+        self.size += size;
+
+        if let Some((first, rest)) = frames.split_first() {
+            self.children_map
+                .entry(first.to_string())
+                .or_insert_with(|| Node {
+                    name: first.to_string(),
+                    ..Default::default()
+                })
+                .insert(rest, size);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.children = std::mem::take(&mut self.children_map)
+            .into_values()
+            .collect();
+        self.children.sort_by(|a, b| b.size.cmp(&a.size));
+
+        for child in &mut self.children {
+            child.finish();
+        }
+    }
+}
+
+fn render_treemap(collapsed: &str) -> Result<String> {
+    let mut root = Node {
+        name: "all".into(),
+        ..Default::default()
+    };
+
+    for line in collapsed.lines() {
+        let Some((frames, size)) = line.rsplit_once(' ') else {
+            continue;
+        };
+        let Ok(size) = size.parse() else {
+            continue;
+        };
+
+        root.insert(&frames.split(';').collect::<Vec<_>>(), size);
+    }
+    root.finish();
+
+    // `</script>` in a symbol name would end the script.
+    let data = serde_json::to_string(&root)
+        .context("failed to serialize the treemap")?
+        .replace("</", "<\\/");
+
+    Ok(TREEMAP_TEMPLATE.replace("/*DATA*/null", &data))
+}
+
+const TREEMAP_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Binary size</title>
+<style>
+  body { margin: 0; font: 12px sans-serif; }
+  #path { padding: 6px; background: #333; color: #fff; cursor: pointer; }
+  #map { position: absolute; top: 28px; left: 0; right: 0; bottom: 0; }
+  .node { position: absolute; box-sizing: border-box; overflow: hidden;
+          border: 1px solid #fff; padding: 2px; cursor: pointer; white-space: nowrap; }
+</style>
+</head>
+<body>
+<div id="path"></div>
+<div id="map"></div>
+<script>
+const root = /*DATA*/null;
+const map = document.getElementById("map");
+const path = document.getElementById("path");
+const stack = [root];
+
+function fmt(size) {
+  const units = ["B", "kB", "MB", "GB"];
+  let i = 0;
+  while (size >= 1000 && i < units.length - 1) { size /= 1000; i++; }
+  return size.toFixed(i ? 2 : 0) + " " + units[i];
+}
+
+function color(name) {
+  let h = 0;
+  for (const c of name) h = (h * 31 + c.charCodeAt(0)) % 360;
+  return "hsl(" + h + ", 60%, 75%)";
+}
+
+// Slice-and-dice layout, alternating the direction at each level.
+function layout(node, x, y, w, h, depth, out) {
+  const children = node.children.filter(c => c.size > 0);
+  if (depth > 1 || children.length === 0 || w < 4 || h < 4) {
+    out.push({ node, x, y, w, h });
+    return;
+  }
+  let offset = 0;
+  for (const child of children) {
+    const ratio = child.size / node.size;
+    if (w > h) {
+      layout(child, x + offset, y, w * ratio, h, depth + 1, out);
+      offset += w * ratio;
+    } else {
+      layout(child, x, y + offset, w, h * ratio, depth + 1, out);
+      offset += h * ratio;
+    }
+  }
+}
+
+function render() {
+  const current = stack[stack.length - 1];
+  path.textContent = stack.map(n => n.name).join(" / ") + " (" + fmt(current.size) + ")";
+  map.innerHTML = "";
+  const out = [];
+  layout(current, 0, 0, map.clientWidth, map.clientHeight, 0, out);
+  for (const { node, x, y, w, h } of out) {
+    const div = document.createElement("div");
+    div.className = "node";
+    div.style.left = x + "px";
+    div.style.top = y + "px";
+    div.style.width = w + "px";
+    div.style.height = h + "px";
+    div.style.background = color(node.name);
+    div.textContent = node.name + " " + fmt(node.size);
+    div.title = node.name + "\n" + fmt(node.size);
+    div.onclick = () => {
+      if (node.children.length) {
+        // Zoom into the clicked node, including the skipped levels.
+        const chain = [];
+        for (let n = node; n !== current; n = parentOf(current, n)) chain.unshift(n);
+        stack.push(...chain);
+        render();
+      }
+    };
+    map.appendChild(div);
+  }
+}
+
+function parentOf(from, target) {
+  for (const child of from.children) {
+    if (child === target) return from;
+    const found = parentOf(child, target);
+    if (found) return found;
+  }
+  return null;
+}
+
+path.onclick = () => {
+  if (stack.length > 1) {
+    stack.pop();
+    render();
+  }
+};
+window.onresize = render;
+render();
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frames_of_symbol() {
+        assert_eq!(
+            symbol_frames("regex-automata", "regex_automata::meta::Regex::new"),
+            vec!["regex-automata", "meta", "Regex", "new"]
+        );
+        assert_eq!(
+            symbol_frames(
+                "alloc",
+                "<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"
+            ),
+            vec![
+                "alloc",
+                "<alloc::vec::Vec<T> as core::ops::drop::Drop>",
+                "drop"
+            ]
+        );
+        assert_eq!(
+            symbol_frames("core", "core::ptr::drop_in_place<a::B>"),
+            vec!["core", "ptr", "drop_in_place<a::B>"]
+        );
+        assert_eq!(
+            symbol_frames("memchr", "memchr::memchr::memchr"),
+            vec!["memchr", "memchr", "memchr"]
+        );
+        assert_eq!(
+            symbol_frames("[Unknown]", "memcpy"),
+            vec!["[Unknown]", "memcpy"]
+        );
+    }
+}
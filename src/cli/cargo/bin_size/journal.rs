@@ -0,0 +1,412 @@
+//! Records the `Cargo.toml` edits made by `select-per-crate`, so they can be
+//! reverted.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use toml_edit::{DocumentMut, TableLike};
+use tracing::warn;
+
+use super::OptLevel;
+use crate::util::{cargo_build::cargo_target_dir, wrap};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct Journal {
+    sessions: Vec<Session>,
+}
+
+/// The package overrides added to a manifest by one run of
+/// `select-per-crate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Session {
+    pub time: String,
+    pub manifest: PathBuf,
+    pub profile: String,
+    pub packages: Vec<PackageEntry>,
+    /// Tables which did not exist before the session, like
+    /// `profile.release.package`, from the outermost.
+    #[serde(default)]
+    pub created_tables: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct PackageEntry {
+    pub name: String,
+    pub opt_level: String,
+}
+
+impl Journal {
+    fn path() -> Result<PathBuf> {
+        Ok(cargo_target_dir()?
+            .join("ddt-bin-size")
+            .join("journal.json"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        std::fs::write(
+            &path,
+            serde_json::to_string_pretty(self).context("failed to serialize the journal")?,
+        )
+        .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Appends a session to the journal stored in the target directory.
+    pub fn record(session: Session) -> Result<()> {
+        if session.packages.is_empty() {
+            return Ok(());
+        }
+
+        let mut journal = Self::load()?;
+        journal.sessions.push(session);
+        journal.save()
+    }
+}
+
+/// Prints the difference between two versions of a manifest as a unified
+/// diff.
+pub(super) fn print_diff(path: &Path, old: &str, new: &str) {
+    if old == new {
+        println!("No changes to {}", path.display());
+        return;
+    }
+
+    let name = path.display().to_string();
+    print!(
+        "{}",
+        TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(&name, &name)
+    );
+}
+
+/// Revert the `Cargo.toml` edits made by `select-per-crate`.
+///
+/// Only the `profile.*.package` entries added by ddt are removed, with the
+/// tables created by ddt if they are left empty. An entry is kept if its
+/// opt-level was changed since.
+#[derive(Debug, Args)]
+pub(super) struct RevertCommand {
+    /// Revert every recorded session, instead of the latest one.
+    #[clap(long)]
+    all: bool,
+
+    /// Print the changes to the manifest without writing it.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+impl RevertCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            let mut journal = Journal::load()?;
+
+            let count = if self.all {
+                journal.sessions.len()
+            } else {
+                journal.sessions.len().min(1)
+            };
+            if count == 0 {
+                println!("There is nothing to revert");
+                return Ok(());
+            }
+
+            let sessions = journal.sessions.split_off(journal.sessions.len() - count);
+
+            let mut manifests = BTreeMap::<PathBuf, (String, DocumentMut)>::new();
+            for session in sessions.iter().rev() {
+                if !manifests.contains_key(&session.manifest) {
+                    let content =
+                        std::fs::read_to_string(&session.manifest).with_context(|| {
+                            format!("failed to read {}", session.manifest.display())
+                        })?;
+                    let toml = content.parse::<DocumentMut>().with_context(|| {
+                        format!("failed to parse {}", session.manifest.display())
+                    })?;
+                    manifests.insert(session.manifest.clone(), (content, toml));
+                }
+
+                let (_, toml) = manifests.get_mut(&session.manifest).unwrap();
+                revert_session(toml, session);
+            }
+
+            for (path, (original, toml)) in &manifests {
+                let reverted = toml.to_string();
+
+                if self.dry_run {
+                    print_diff(path, original, &reverted);
+                } else {
+                    std::fs::write(path, reverted)
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                }
+            }
+
+            if !self.dry_run {
+                journal.save()?;
+                println!("Reverted {} session(s)", count);
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to revert the bin-size changes")
+    }
+}
+
+/// Removes the package overrides added in `session` from `toml`.
+fn revert_session(toml: &mut DocumentMut, session: &Session) {
+    let Some(package_table) = toml
+        .get_mut("profile")
+        .and_then(|p| p.get_mut(&session.profile))
+        .and_then(|p| p.get_mut("package"))
+        .and_then(|p| p.as_table_like_mut())
+    else {
+        warn!(
+            "`profile.{}.package` is not in {}",
+            session.profile,
+            session.manifest.display()
+        );
+        return;
+    };
+
+    for entry in &session.packages {
+        let Some(package) = package_table
+            .get_mut(&entry.name)
+            .and_then(|p| p.as_table_like_mut())
+        else {
+            continue;
+        };
+
+        let current = package.get("opt-level").and_then(OptLevel::from_toml);
+        if current.map(|v| v.to_string()).as_deref() != Some(&*entry.opt_level) {
+            warn!(
+                "Keeping `profile.{}.package.{}` as its opt-level was changed",
+                session.profile, entry.name
+            );
+            continue;
+        }
+
+        package.remove("opt-level");
+        if package.is_empty() {
+            package_table.remove(&entry.name);
+        }
+    }
+
+    remove_created_tables(toml, &session.created_tables);
+}
+
+/// Removes the tables in `created_tables` which are empty, from the
+/// innermost. Tables with other entries are kept.
+pub(super) fn remove_created_tables(toml: &mut DocumentMut, created_tables: &[String]) {
+    'tables: for path in created_tables.iter().rev() {
+        let keys = path.split('.').collect::<Vec<_>>();
+        let Some((last, parents)) = keys.split_last() else {
+            continue;
+        };
+
+        let mut table = toml.as_table_mut() as &mut dyn TableLike;
+        for key in parents {
+            match table.get_mut(key).and_then(|t| t.as_table_like_mut()) {
+                Some(t) => table = t,
+                None => continue 'tables,
+            }
+        }
+
+        if table
+            .get(last)
+            .and_then(|t| t.as_table_like())
+            .is_some_and(|t| t.is_empty())
+        {
+            table.remove(last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn revert_added_entries() {
+        let mut toml = r#"
+[profile.release]
+lto = true
+
+[profile.release.package.regex]
+opt-level = "s"
+
+[profile.release.package.memchr]
+opt-level = 2
+
+[profile.release.package.serde]
+opt-level = 1
+debug = true
+
+[profile.release.package.log]
+opt-level = 3
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        let session = Session {
+            time: String::new(),
+            manifest: PathBuf::from("Cargo.toml"),
+            profile: "release".into(),
+            packages: ["regex:s", "memchr:z", "serde:1"]
+                .iter()
+                .map(|s| {
+                    let (name, opt_level) = s.split_once(':').unwrap();
+                    PackageEntry {
+                        name: name.into(),
+                        opt_level: opt_level.into(),
+                    }
+                })
+                .collect(),
+            created_tables: vec![],
+        };
+
+        revert_session(&mut toml, &session);
+
+        // The package table existed before.
+        let empty = Session {
+            packages: vec![],
+            ..session
+        };
+        revert_session(&mut toml, &empty);
+
+        assert_eq!(
+            toml.to_string(),
+            r#"
+[profile.release]
+lto = true
+
+[profile.release.package.memchr]
+opt-level = 2
+
+[profile.release.package.serde]
+debug = true
+
+[profile.release.package.log]
+opt-level = 3
+"#
+        );
+    }
+
+    #[test]
+    fn remove_only_created_tables() {
+        let session = |profile: &str, created_tables: &[&str]| Session {
+            time: String::new(),
+            manifest: PathBuf::from("Cargo.toml"),
+            profile: profile.into(),
+            packages: vec![PackageEntry {
+                name: "regex".into(),
+                opt_level: "s".into(),
+            }],
+            created_tables: created_tables.iter().map(|s| s.to_string()).collect(),
+        };
+
+        // An empty package table which existed before is kept.
+        let mut toml = r#"
+[package]
+name = "app"
+
+[profile.release.package]
+
+[profile.release.package.regex]
+opt-level = "s"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        revert_session(&mut toml, &session("release", &[]));
+        assert_eq!(
+            toml.to_string(),
+            r#"
+[package]
+name = "app"
+
+[profile.release.package]
+"#
+        );
+
+        // The profile created by ddt is removed.
+        let mut toml = r#"
+[package]
+name = "app"
+
+[profile.release]
+lto = true
+
+[profile.small]
+inherits = "release"
+
+[profile.small.package.regex]
+opt-level = "s"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        revert_session(
+            &mut toml,
+            &session("small", &["profile.small", "profile.small.package"]),
+        );
+        assert_eq!(
+            toml.to_string(),
+            r#"
+[package]
+name = "app"
+
+[profile.release]
+lto = true
+
+[profile.small]
+inherits = "release"
+"#
+        );
+
+        let mut toml = r#"
+[package]
+name = "app"
+
+[profile.release.package.regex]
+opt-level = "s"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        revert_session(
+            &mut toml,
+            &session(
+                "release",
+                &["profile", "profile.release", "profile.release.package"],
+            ),
+        );
+        assert_eq!(
+            toml.to_string(),
+            r#"
+[package]
+name = "app"
+"#
+        );
+    }
+}
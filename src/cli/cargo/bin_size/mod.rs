@@ -1,22 +1,43 @@
-use std::fmt::{self, Display};
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::{Args, Subcommand};
+use futures::future::try_join_all;
 use hstr::Atom;
 use humansize::{format_size, DECIMAL};
 use indexmap::IndexMap;
 use rustc_hash::FxBuildHasher;
-use serde::Deserialize;
-use toml_edit::{table, value, DocumentMut};
-
+use toml_edit::{table, value, DocumentMut, Item};
+
+use self::{
+    analyze::{check_build_target, crate_sizes, Analyzer},
+    bench::{format_runtime, BenchArgs, RestoreManifest},
+    check::CheckCommand,
+    diff::DiffCommand,
+    graph::GraphCommand,
+    journal::{print_diff, remove_created_tables, Journal, PackageEntry, RevertCommand, Session},
+    policy::{PolicyArgs, DEFAULT_CANDIDATES},
+    sections::SectionsCommand,
+};
 use crate::{
     cli::util::cargo::to_original_crate_name,
-    util::{
-        cargo_build::{cargo_root_manifest, CargoBuildTarget},
-        ensure_cargo_subcommand, PrettyCmd,
-    },
+    util::cargo_build::{cargo_root_manifest, CargoBuildTarget, DiagnosticsArgs},
 };
 
+pub(super) mod analyze;
+pub(super) mod bench;
+pub(super) mod bloat;
+mod check;
+mod diff;
+mod graph;
+mod journal;
+mod policy;
+mod sections;
+
 /// Comamnds to reduce the size of the binary.
 #[derive(Debug, Args)]
 pub(super) struct BinSizeCommand {
@@ -28,6 +49,11 @@ impl BinSizeCommand {
     pub async fn run(self) -> Result<()> {
         match self.cmd {
             Cmd::SelectPerCrate(cmd) => cmd.run().await,
+            Cmd::Diff(cmd) => cmd.run().await,
+            Cmd::Check(cmd) => cmd.run().await,
+            Cmd::Graph(cmd) => cmd.run().await,
+            Cmd::Sections(cmd) => cmd.run().await,
+            Cmd::Revert(cmd) => cmd.run().await,
         }
     }
 }
@@ -35,20 +61,57 @@ impl BinSizeCommand {
 #[derive(Debug, Subcommand)]
 enum Cmd {
     SelectPerCrate(SelectPerCrateCommand),
+    Diff(DiffCommand),
+    Check(CheckCommand),
+    Graph(GraphCommand),
+    Sections(SectionsCommand),
+    Revert(RevertCommand),
 }
 
 /// Select the optimization level for each crate.
+///
+/// With `--bench-cmd`, the benchmark runs for each crate with an opt-level
+/// override for the crate written to the root `Cargo.toml`. Only the opt-levels
+/// smaller than the opt-level of the profile are benchmarked.
+///
+/// The added overrides are recorded in the target directory, and can be removed
+/// with `ddt cargo bin-size revert`.
 #[derive(Debug, Args)]
 struct SelectPerCrateCommand {
     #[clap(long)]
     compare: bool,
 
+    /// Print the changes to the root `Cargo.toml` without writing it.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Opt-levels to compare. All opt-levels are compared by default.
+    #[clap(long, value_delimiter = ',')]
+    opt_levels: Vec<OptLevel>,
+
+    /// Tool used to measure the size of each crate.
+    #[clap(long, value_enum, default_value = "native")]
+    analyzer: Analyzer,
+
+    #[clap(flatten)]
+    policy: PolicyArgs,
+
+    #[clap(flatten)]
+    bench: BenchArgs,
+
     #[clap(flatten)]
     build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
 }
 
 impl SelectPerCrateCommand {
     pub async fn run(self) -> Result<()> {
+        if self.compare || self.policy.policy {
+            check_build_target(self.analyzer, &self.build_target)?;
+        }
+
         let root_cargo_toml_path =
             cargo_root_manifest().context("failed to get the root cargo.toml")?;
         let root_content = std::fs::read_to_string(&root_cargo_toml_path)
@@ -58,10 +121,15 @@ impl SelectPerCrateCommand {
             .parse::<DocumentMut>()
             .context("failed to parse the root cargo.toml")?;
 
-        let profile_name = self.build_target.profile.as_deref().unwrap_or("release");
+        let profile_name = self.build_target.profile_name();
+
+        // Recorded in the journal, so `revert` removes only the tables created
+        // here.
+        let mut created_tables = vec![];
 
-        if !toml["profile"].is_table() {
+        if !toml.get("profile").is_some_and(|p| p.is_table()) {
             toml["profile"] = table();
+            created_tables.push("profile".to_string());
         }
 
         if !toml["profile"]
@@ -70,6 +138,7 @@ impl SelectPerCrateCommand {
             .contains_key(profile_name)
         {
             toml["profile"][profile_name] = table();
+            created_tables.push(format!("profile.{}", profile_name));
         }
 
         if !toml["profile"][profile_name].is_table() {
@@ -82,31 +151,47 @@ impl SelectPerCrateCommand {
             .contains_key("package")
         {
             toml["profile"][profile_name]["package"] = table();
+            created_tables.push(format!("profile.{}.package", profile_name));
         }
 
-        let package_table = toml["profile"][profile_name]["package"]
-            .as_table_mut()
-            .context("failed to get the package table")?;
+        let current_opt_level = current_opt_level(&toml, profile_name);
 
         let mut crates = IndexMap::<Atom, _, FxBuildHasher>::default();
 
-        if self.compare {
-            ensure_cargo_subcommand("bloat")
-                .await
-                .context("You can install bloat by `cargo install cargo-bloat`")?;
-
-            let for_perf = run_bloat(&self.build_target, OptLevel::Performance).await?;
-            let for_size = run_bloat(&self.build_target, OptLevel::Size).await?;
+        if self.compare || self.policy.policy {
+            let mut opt_levels = if self.opt_levels.is_empty() {
+                OptLevel::ALL.to_vec()
+            } else {
+                self.opt_levels.clone()
+            };
+            // Required to compute the deltas.
+            opt_levels.push(current_opt_level);
+            if self.policy.policy {
+                opt_levels.push(OptLevel::Performance);
+            }
+            opt_levels.sort();
+            opt_levels.dedup();
+
+            // Each opt-level uses a separate target directory, so we can build them
+            // concurrently.
+            let json_output = self.diagnostics.open().await?;
+            let outputs = try_join_all(opt_levels.iter().map(|&opt_level| {
+                crate_sizes(
+                    self.analyzer,
+                    &self.build_target,
+                    Some(opt_level),
+                    json_output.as_ref(),
+                )
+            }))
+            .await?;
 
-            for (opt_level, output) in [
-                (OptLevel::Performance, for_perf),
-                (OptLevel::Size, for_size),
-            ] {
-                for crate_ in output.crates {
+            for (opt_level, output) in opt_levels.into_iter().zip(outputs) {
+                for crate_ in output {
                     let info = crates
                         .entry(crate_.name.clone())
                         .or_insert_with(|| CrateInfo {
                             size: PerOptLevel::default(),
+                            runtime: PerOptLevel::default(),
                         });
 
                     info.size.insert(opt_level, crate_.size);
@@ -115,6 +200,9 @@ impl SelectPerCrateCommand {
         }
 
         // Remove from the crate list if it's already in the package table.
+        let package_table = toml["profile"][profile_name]["package"]
+            .as_table()
+            .context("failed to get the package table")?;
         crates.retain(|name, _| {
             let Ok(name) = to_original_crate_name(name.clone()) else {
                 return true;
@@ -130,148 +218,311 @@ impl SelectPerCrateCommand {
             !sizes.iter().all(|size| size == &sizes[0])
         });
 
-        for (name, info) in crates {
-            let Ok(name) = to_original_crate_name(name) else {
-                continue;
+        let baseline = if self.bench.bench_cmd.is_some() {
+            // Benchmarking overwrites the root cargo.toml.
+            let _restore = RestoreManifest {
+                path: root_cargo_toml_path.clone(),
+                content: root_content.clone(),
             };
 
-            let selected = dialoguer::Select::new()
-                .with_prompt(format!(
-                    "Select the optimization level for {} (Esc to skip)",
-                    name
-                ))
-                .items(
-                    &info
-                        .size
-                        .iter()
-                        .map(|(k, v)| format!("{}: {}", k, format_size(*v, DECIMAL)))
-                        .collect::<Vec<_>>(),
+            let baseline = self
+                .bench
+                .measure(
+                    &root_cargo_toml_path,
+                    &toml,
+                    &format!("opt-level = {}", current_opt_level),
                 )
-                .interact_opt()
-                .context("failed to select the optimization level")?;
+                .await?;
 
-            if let Some(selected) = selected {
-                let (selected_opt_level, _) = info.size.get_index(selected).unwrap();
+            for (name, info) in crates.iter_mut() {
+                let Ok(name) = to_original_crate_name(name.clone()) else {
+                    continue;
+                };
 
-                let mut t = table();
-                {
-                    t.as_table_mut().unwrap().insert(
-                        "opt-level",
-                        match selected_opt_level {
-                            OptLevel::Performance => value(3),
-                            OptLevel::Size => value("s"),
-                            OptLevel::SizeWithLoopVec => value("z"),
-                        },
-                    );
+                if let Some(baseline) = baseline {
+                    info.runtime.insert(current_opt_level, baseline);
                 }
 
-                package_table[&*name] = t;
+                let current_size = info.size.get(&current_opt_level).copied();
+
+                for (&opt_level, &size) in &info.size {
+                    // Larger opt-levels are not worth benchmarking.
+                    if opt_level == current_opt_level
+                        || current_size.is_some_and(|current_size| size >= current_size)
+                    {
+                        continue;
+                    }
+
+                    let mut candidate = toml.clone();
+                    candidate["profile"][profile_name]["package"][&*name]["opt-level"] =
+                        opt_level.to_toml();
+
+                    let runtime = self
+                        .bench
+                        .measure(
+                            &root_cargo_toml_path,
+                            &candidate,
+                            &format!("{} with opt-level = {}", name, opt_level),
+                        )
+                        .await?;
+
+                    if let Some(runtime) = runtime {
+                        info.runtime.insert(opt_level, runtime);
+                    }
+                }
             }
-        }
-
-        std::fs::write(root_cargo_toml_path, toml.to_string())
-            .context("failed to write the root cargo.toml")?;
 
-        Ok(())
-    }
-}
+            baseline
+        } else {
+            None
+        };
 
-async fn run_bloat(build_target: &CargoBuildTarget, opt_level: OptLevel) -> Result<BloatOutput> {
-    let mut cmd = PrettyCmd::new("Running cargo bloat", "cargo");
-    cmd.arg("bloat");
+        let package_table = toml["profile"][profile_name]["package"]
+            .as_table_mut()
+            .context("failed to get the package table")?;
 
-    cmd.arg("--crates");
-    // Show all crates
-    cmd.arg("-n").arg("0");
+        let candidates = if self.opt_levels.is_empty() {
+            &DEFAULT_CANDIDATES[..]
+        } else {
+            &self.opt_levels
+        };
 
-    // Ouptut in json format.
-    cmd.arg("--message-format").arg("json");
+        let mut choices = vec![];
 
-    cmd.env("CARGO_PROFILE_RELEASE_DEBUG", "1");
-    cmd.env("CARGO_PROFILE_RELEASE_OPT_LEVEL", opt_level.to_string());
+        for (name, info) in crates {
+            let Ok(name) = to_original_crate_name(name) else {
+                continue;
+            };
 
-    if build_target.release {
-        cmd.arg("--release");
-    }
+            let selected_opt_level = if self.policy.policy {
+                self.policy
+                    .select(&name, &info.size, &info.runtime, baseline, candidates)
+            } else {
+                let selected = dialoguer::Select::new()
+                    .with_prompt(format!(
+                        "Select the optimization level for {} (Esc to skip)",
+                        name
+                    ))
+                    .items(
+                        &info
+                            .size
+                            .iter()
+                            .map(|(k, v)| {
+                                format!(
+                                    "{}: {}{}{}",
+                                    k,
+                                    format_size(*v, DECIMAL),
+                                    format_delta(&info.size, current_opt_level, *v),
+                                    format_runtime(info.runtime.get(k).copied(), baseline)
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .interact_opt()
+                    .context("failed to select the optimization level")?;
+
+                selected.map(|selected| *info.size.get_index(selected).unwrap().0)
+            };
 
-    if build_target.lib {
-        cmd.arg("--lib");
-    }
+            if let Some(selected_opt_level) = selected_opt_level {
+                let mut t = table();
+                {
+                    t.as_table_mut()
+                        .unwrap()
+                        .insert("opt-level", selected_opt_level.to_toml());
+                }
 
-    if build_target.bin.is_some() {
-        cmd.arg("--bin").arg(build_target.bin.as_ref().unwrap());
-    }
+                package_table[&*name] = t;
 
-    if build_target.benches {
-        cmd.arg("--benches");
-    }
+                choices.push((name, selected_opt_level, info));
+            }
+        }
 
-    if let Some(bench) = &build_target.bench {
-        cmd.arg("--bench").arg(bench);
-    }
+        if choices.is_empty() {
+            remove_created_tables(&mut toml, &created_tables);
+        }
 
-    if build_target.tests {
-        cmd.arg("--tests");
-    }
+        let new_content = toml.to_string();
+
+        if self.dry_run {
+            print_diff(&root_cargo_toml_path, &root_content, &new_content);
+        } else {
+            std::fs::write(&root_cargo_toml_path, new_content)
+                .context("failed to write the root cargo.toml")?;
+
+            Journal::record(Session {
+                time: chrono::Local::now().to_rfc3339(),
+                manifest: root_cargo_toml_path,
+                profile: profile_name.to_string(),
+                packages: choices
+                    .iter()
+                    .map(|(name, opt_level, _)| PackageEntry {
+                        name: name.to_string(),
+                        opt_level: opt_level.to_string(),
+                    })
+                    .collect(),
+                created_tables,
+            })
+            .context("failed to record the changes")?;
+        }
 
-    if let Some(test) = &build_target.test {
-        cmd.arg("--test").arg(test);
-    }
+        print_summary(&choices, current_opt_level, baseline);
 
-    if build_target.examples {
-        cmd.arg("--examples");
+        Ok(())
     }
+}
 
-    if let Some(example) = &build_target.example {
-        cmd.arg("--example").arg(example);
+/// Prints the selected opt-levels and the estimated size saving, compared to
+/// the opt-level of the profile.
+fn print_summary(
+    choices: &[(Atom, OptLevel, CrateInfo)],
+    current_opt_level: OptLevel,
+    baseline: Option<Duration>,
+) {
+    if choices.is_empty() {
+        println!("No opt-level was selected");
+        return;
     }
 
-    if let Some(features) = &build_target.features {
-        cmd.arg("--features").arg(features.join(","));
+    let width = choices
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut total_saving = 0i64;
+
+    for (name, opt_level, info) in choices {
+        let selected = info.size.get(opt_level).copied().unwrap_or_default();
+        let base = info
+            .size
+            .get(&current_opt_level)
+            .copied()
+            .unwrap_or(selected);
+        total_saving += base as i64 - selected as i64;
+
+        println!(
+            "{:width$}  opt-level = {}  {}",
+            name,
+            opt_level,
+            info.size
+                .iter()
+                .map(|(k, v)| format!(
+                    "{}: {}{}{}",
+                    k,
+                    format_size(*v, DECIMAL),
+                    format_delta(&info.size, current_opt_level, *v),
+                    format_runtime(info.runtime.get(k).copied(), baseline)
+                ))
+                .collect::<Vec<_>>()
+                .join(", "),
+            width = width
+        );
     }
 
-    if let Some(profile) = &build_target.profile {
-        cmd.arg("--profile").arg(profile);
-    }
+    println!(
+        "Selected opt-level for {} crates. Estimated saving compared to opt-level = {}: {}{}",
+        choices.len(),
+        current_opt_level,
+        if total_saving < 0 { "-" } else { "" },
+        format_size(total_saving.unsigned_abs(), DECIMAL)
+    );
+}
 
-    let output = cmd.output().await.context("failed to run cargo bloat")?;
+/// Formats the difference between `size` and the size for the current
+/// opt-level.
+fn format_delta(sizes: &PerOptLevel<u64>, current_opt_level: OptLevel, size: u64) -> String {
+    let Some(&base) = sizes.get(&current_opt_level) else {
+        return String::new();
+    };
 
-    let output: BloatOutput =
-        serde_json::from_str(&output).context("failed to parse bloat output")?;
+    let delta = size as i64 - base as i64;
+    if delta == 0 {
+        return String::new();
+    }
 
-    Ok(output)
+    format!(
+        " ({}{})",
+        if delta < 0 { "-" } else { "+" },
+        format_size(delta.unsigned_abs(), DECIMAL)
+    )
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-struct BloatOutput {
-    // file_size: u64,
-    // text_section_size: u64,
-    crates: Vec<BloatCrate>,
-}
+/// Reads the opt-level of the profile, following `inherits`.
+fn current_opt_level(toml: &DocumentMut, profile_name: &str) -> OptLevel {
+    let profile = toml.get("profile").and_then(|p| p.get(profile_name));
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-struct BloatCrate {
-    name: Atom,
-    /// File size in bytes.
-    size: u64,
+    if let Some(opt_level) = profile
+        .and_then(|p| p.get("opt-level"))
+        .and_then(OptLevel::from_toml)
+    {
+        return opt_level;
+    }
+
+    match profile_name {
+        "release" | "bench" => OptLevel::Performance,
+        "dev" | "test" => OptLevel::NoOptimization,
+        _ => match profile
+            .and_then(|p| p.get("inherits"))
+            .and_then(|v| v.as_str())
+        {
+            Some(parent) if parent != profile_name => current_opt_level(toml, parent),
+            _ => OptLevel::Performance,
+        },
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum OptLevel {
+    /// `0`
+    NoOptimization,
+    /// `1`
+    Basic,
+    /// `2`
+    Moderate,
     /// `3`
     Performance,
     /// `s`
     Size,
     /// `z`
-    #[allow(unused)]
     SizeWithLoopVec,
 }
 
+impl OptLevel {
+    const ALL: [OptLevel; 6] = [
+        OptLevel::NoOptimization,
+        OptLevel::Basic,
+        OptLevel::Moderate,
+        OptLevel::Performance,
+        OptLevel::Size,
+        OptLevel::SizeWithLoopVec,
+    ];
+
+    fn to_toml(self) -> Item {
+        match self {
+            OptLevel::NoOptimization => value(0),
+            OptLevel::Basic => value(1),
+            OptLevel::Moderate => value(2),
+            OptLevel::Performance => value(3),
+            OptLevel::Size => value("s"),
+            OptLevel::SizeWithLoopVec => value("z"),
+        }
+    }
+
+    fn from_toml(item: &Item) -> Option<Self> {
+        if let Some(v) = item.as_integer() {
+            return v.to_string().parse().ok();
+        }
+
+        item.as_str()?.parse().ok()
+    }
+}
+
 impl Display for OptLevel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            OptLevel::NoOptimization => write!(f, "0"),
+            OptLevel::Basic => write!(f, "1"),
+            OptLevel::Moderate => write!(f, "2"),
             OptLevel::Performance => write!(f, "3"),
             OptLevel::Size => write!(f, "s"),
             OptLevel::SizeWithLoopVec => write!(f, "z"),
@@ -279,9 +530,52 @@ impl Display for OptLevel {
     }
 }
 
+impl FromStr for OptLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "0" => OptLevel::NoOptimization,
+            "1" => OptLevel::Basic,
+            "2" => OptLevel::Moderate,
+            "3" => OptLevel::Performance,
+            "s" => OptLevel::Size,
+            "z" => OptLevel::SizeWithLoopVec,
+            _ => bail!("invalid opt-level `{}`", s),
+        })
+    }
+}
+
+/// Parses sizes like `1024`, `10KB` or `1.5MiB`.
+fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let idx = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(idx);
+
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("invalid size `{}`", s))?;
+
+    let unit = unit.trim();
+    let multiplier = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "kib" => 1024,
+        "mib" => 1024 * 1024,
+        _ => bail!("unknown size unit `{}`", unit),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
 type PerOptLevel<T> = IndexMap<OptLevel, T, FxBuildHasher>;
 
 #[derive(Debug)]
 struct CrateInfo {
     size: PerOptLevel<u64>,
+    /// Runtime of the benchmark, if `--bench-cmd` is given.
+    runtime: PerOptLevel<Duration>,
 }
@@ -0,0 +1,277 @@
+use std::time::Duration;
+
+use clap::Args;
+
+use super::{bench::slowdown_percent, parse_size, OptLevel, PerOptLevel};
+
+/// Options to select the opt-level of each crate without prompting.
+#[derive(Debug, Args)]
+pub(super) struct PolicyArgs {
+    /// Select the opt-level of each crate automatically, instead of prompting.
+    ///
+    /// The smallest opt-level is selected if it satisfies any of the
+    /// thresholds, or if no threshold is given and it is smaller than `3`.
+    /// Only `s` and `z` are selected, unless other opt-levels are given by
+    /// `--opt-levels`.
+    #[clap(long)]
+    pub policy: bool,
+
+    /// Select the smallest opt-level if it reduces the size of the crate by
+    /// this percentage, compared to `3`.
+    #[clap(long, requires = "policy")]
+    min_saving_percent: Option<f64>,
+
+    /// Select the smallest opt-level if it reduces the size of the crate by
+    /// this amount, compared to `3`. e.g. `10KB`
+    #[clap(long, requires = "policy", value_parser = parse_size)]
+    min_saving_size: Option<u64>,
+
+    /// Crates which always use `3`.
+    #[clap(long = "hot", requires = "policy")]
+    hot_crates: Vec<String>,
+
+    /// Only select opt-levels which slow down the benchmark by at most this
+    /// percentage, compared to the opt-level of the profile.
+    #[clap(long, requires_all = ["policy", "bench_cmd"])]
+    max_slowdown_percent: Option<f64>,
+}
+
+/// The opt-levels the policy selects from, if `--opt-levels` is not given.
+/// `0`, `1` and `2` would trade a lot of performance for a few bytes.
+pub(super) const DEFAULT_CANDIDATES: [OptLevel; 2] = [OptLevel::Size, OptLevel::SizeWithLoopVec];
+
+impl PolicyArgs {
+    /// Returns [None] if the crate should use the opt-level of the profile.
+    ///
+    /// Only the opt-levels in `candidates` are selected. `baseline` is the
+    /// runtime of the benchmark with the opt-level of the profile.
+    pub fn select(
+        &self,
+        name: &str,
+        sizes: &PerOptLevel<u64>,
+        runtimes: &PerOptLevel<Duration>,
+        baseline: Option<Duration>,
+        candidates: &[OptLevel],
+    ) -> Option<OptLevel> {
+        if self.hot_crates.iter().any(|c| c == name) {
+            return Some(OptLevel::Performance);
+        }
+
+        let base = *sizes.get(&OptLevel::Performance)?;
+        let (&smallest, &size) = sizes
+            .iter()
+            .filter(|(opt_level, _)| {
+                (**opt_level == OptLevel::Performance || candidates.contains(opt_level))
+                    && self.is_fast_enough(**opt_level, runtimes, baseline)
+            })
+            .min_by_key(|(_, size)| **size)?;
+
+        if smallest == OptLevel::Performance {
+            return None;
+        }
+
+        let saving = base.saturating_sub(size);
+
+        if self.min_saving_percent.is_none() && self.min_saving_size.is_none() {
+            return (saving > 0).then_some(smallest);
+        }
+
+        let by_percent = self
+            .min_saving_percent
+            .is_some_and(|p| base > 0 && saving as f64 * 100.0 / base as f64 >= p);
+        let by_size = self.min_saving_size.is_some_and(|s| saving >= s);
+
+        (by_percent || by_size).then_some(smallest)
+    }
+
+    fn is_fast_enough(
+        &self,
+        opt_level: OptLevel,
+        runtimes: &PerOptLevel<Duration>,
+        baseline: Option<Duration>,
+    ) -> bool {
+        let Some(max_slowdown_percent) = self.max_slowdown_percent else {
+            return true;
+        };
+
+        if opt_level == OptLevel::Performance {
+            return true;
+        }
+
+        match (runtimes.get(&opt_level), baseline) {
+            (Some(&runtime), Some(baseline)) => {
+                slowdown_percent(runtime, baseline) <= max_slowdown_percent
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy() -> PolicyArgs {
+        PolicyArgs {
+            policy: true,
+            min_saving_percent: None,
+            min_saving_size: None,
+            hot_crates: vec![],
+            max_slowdown_percent: None,
+        }
+    }
+
+    fn sizes(sizes: &[(OptLevel, u64)]) -> PerOptLevel<u64> {
+        sizes.iter().copied().collect()
+    }
+
+    fn runtimes(runtimes: &[(OptLevel, u64)]) -> PerOptLevel<Duration> {
+        runtimes
+            .iter()
+            .map(|&(opt_level, ms)| (opt_level, Duration::from_millis(ms)))
+            .collect()
+    }
+
+    #[test]
+    fn select_smallest() {
+        let sizes = sizes(&[
+            (OptLevel::Performance, 1000),
+            (OptLevel::Size, 800),
+            (OptLevel::SizeWithLoopVec, 700),
+        ]);
+        let none = PerOptLevel::default();
+
+        assert_eq!(
+            policy().select("regex", &sizes, &none, None, &DEFAULT_CANDIDATES),
+            Some(OptLevel::SizeWithLoopVec)
+        );
+
+        let hot = PolicyArgs {
+            hot_crates: vec!["regex".into()],
+            ..policy()
+        };
+        assert_eq!(
+            hot.select("regex", &sizes, &none, None, &DEFAULT_CANDIDATES),
+            Some(OptLevel::Performance)
+        );
+        assert_eq!(
+            hot.select("serde", &sizes, &none, None, &DEFAULT_CANDIDATES),
+            Some(OptLevel::SizeWithLoopVec)
+        );
+
+        // `3` is already the smallest.
+        let larger = self::sizes(&[(OptLevel::Performance, 1000), (OptLevel::Size, 1000)]);
+        assert_eq!(
+            policy().select("regex", &larger, &none, None, &DEFAULT_CANDIDATES),
+            None
+        );
+
+        // Ties are broken by the order of the measurements.
+        let tie = self::sizes(&[
+            (OptLevel::Performance, 1000),
+            (OptLevel::Size, 700),
+            (OptLevel::SizeWithLoopVec, 700),
+        ]);
+        assert_eq!(
+            policy().select("regex", &tie, &none, None, &DEFAULT_CANDIDATES),
+            Some(OptLevel::Size)
+        );
+    }
+
+    #[test]
+    fn saving_thresholds() {
+        let sizes = sizes(&[(OptLevel::Performance, 1000), (OptLevel::Size, 900)]);
+        let none = PerOptLevel::default();
+
+        let select = |min_saving_percent, min_saving_size| {
+            PolicyArgs {
+                min_saving_percent,
+                min_saving_size,
+                ..policy()
+            }
+            .select("regex", &sizes, &none, None, &DEFAULT_CANDIDATES)
+        };
+
+        assert_eq!(select(Some(10.0), None), Some(OptLevel::Size));
+        assert_eq!(select(Some(10.1), None), None);
+        assert_eq!(select(None, Some(100)), Some(OptLevel::Size));
+        assert_eq!(select(None, Some(101)), None);
+        // Any of the thresholds.
+        assert_eq!(select(Some(50.0), Some(100)), Some(OptLevel::Size));
+        assert_eq!(select(Some(50.0), Some(500)), None);
+    }
+
+    #[test]
+    fn max_slowdown() {
+        let sizes = sizes(&[
+            (OptLevel::Performance, 1000),
+            (OptLevel::Size, 800),
+            (OptLevel::SizeWithLoopVec, 700),
+        ]);
+        let runtimes = runtimes(&[(OptLevel::Size, 105), (OptLevel::SizeWithLoopVec, 130)]);
+        let baseline = Some(Duration::from_millis(100));
+
+        let policy = PolicyArgs {
+            max_slowdown_percent: Some(10.0),
+            ..policy()
+        };
+
+        // `z` is smaller, but too slow.
+        assert_eq!(
+            policy.select("regex", &sizes, &runtimes, baseline, &DEFAULT_CANDIDATES),
+            Some(OptLevel::Size)
+        );
+
+        // Opt-levels without a measurement are not selected.
+        assert_eq!(
+            policy.select(
+                "regex",
+                &sizes,
+                &PerOptLevel::default(),
+                baseline,
+                &DEFAULT_CANDIDATES
+            ),
+            None
+        );
+        assert_eq!(
+            policy.select("regex", &sizes, &runtimes, None, &DEFAULT_CANDIDATES),
+            None
+        );
+    }
+
+    #[test]
+    fn only_size_levels_by_default() {
+        let sizes = sizes(&[
+            (OptLevel::NoOptimization, 500),
+            (OptLevel::Basic, 600),
+            (OptLevel::Moderate, 850),
+            (OptLevel::Performance, 1000),
+            (OptLevel::Size, 900),
+            (OptLevel::SizeWithLoopVec, 800),
+        ]);
+        let none = PerOptLevel::default();
+
+        let selected = policy().select("regex", &sizes, &none, None, &DEFAULT_CANDIDATES);
+        assert!(
+            !matches!(
+                selected,
+                Some(OptLevel::NoOptimization | OptLevel::Basic | OptLevel::Moderate)
+            ),
+            "selected {:?}",
+            selected
+        );
+        assert_eq!(selected, Some(OptLevel::SizeWithLoopVec));
+
+        // Allowed by `--opt-levels`.
+        assert_eq!(
+            policy().select(
+                "regex",
+                &sizes,
+                &none,
+                None,
+                &[OptLevel::Basic, OptLevel::Size]
+            ),
+            Some(OptLevel::Basic)
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use humansize::{format_size, DECIMAL};
+use serde::Serialize;
+
+use super::analyze::{analyze_build, Section, SectionCategory};
+use crate::util::{
+    cargo_build::{CargoBuildTarget, DiagnosticsArgs},
+    wrap,
+};
+
+/// Break down the binary by section, and estimate what `strip` and
+/// `split-debuginfo` would save.
+#[derive(Debug, Args)]
+pub(super) struct SectionsCommand {
+    #[clap(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    file_size: u64,
+    sections: Vec<Section>,
+    categories: BTreeMap<SectionCategory, u64>,
+    /// Headers, and the data which is not in any section like the symbol
+    /// table of Mach-O.
+    other: u64,
+    savings: Vec<Saving>,
+}
+
+#[derive(Debug, Serialize)]
+struct Saving {
+    /// Profile setting.
+    setting: &'static str,
+    size: u64,
+    note: &'static str,
+}
+
+impl SectionsCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            if let OutputFormat::Json = self.format {
+                self.diagnostics.ensure_not_stdout()?;
+            }
+
+            let json_output = self.diagnostics.open().await?;
+            let analysis = analyze_build(&self.build_target, None, json_output.as_ref()).await?;
+
+            let mut categories = BTreeMap::<_, u64>::new();
+            for section in &analysis.sections {
+                *categories.entry(section.category).or_default() += section.size;
+            }
+
+            let in_sections = categories.values().sum::<u64>();
+            let debug = categories
+                .get(&SectionCategory::Debug)
+                .copied()
+                .unwrap_or(0);
+            let symbols = categories
+                .get(&SectionCategory::Symbols)
+                .copied()
+                .unwrap_or(0);
+
+            let mut savings = vec![];
+            if debug > 0 {
+                savings.push(Saving {
+                    setting: "strip = \"debuginfo\"",
+                    size: debug,
+                    note: "removes the debug info",
+                });
+                savings.push(Saving {
+                    setting: "split-debuginfo = \"packed\"",
+                    size: debug,
+                    note: "moves the debug info to a separate file",
+                });
+            }
+            if debug + symbols > 0 {
+                savings.push(Saving {
+                    setting: "strip = \"symbols\"",
+                    size: debug + symbols,
+                    note: "removes the debug info and the symbol tables",
+                });
+            }
+
+            let report = Report {
+                file_size: analysis.file_size,
+                sections: analysis.sections,
+                categories,
+                other: analysis.file_size.saturating_sub(in_sections),
+                savings,
+            };
+
+            match self.format {
+                OutputFormat::Text => print_text(&report),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report)
+                        .context("failed to serialize the report")?
+                ),
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to break down the binary by section")
+    }
+}
+
+fn percent(size: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    size as f64 * 100.0 / total as f64
+}
+
+fn print_text(report: &Report) {
+    let width = report
+        .sections
+        .iter()
+        .map(|s| s.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(20);
+
+    println!("Sections:");
+    let mut sections = report
+        .sections
+        .iter()
+        .filter(|s| s.size > 0)
+        .collect::<Vec<_>>();
+    sections.sort_by(|a, b| b.size.cmp(&a.size));
+    for section in sections {
+        println!(
+            "  {:width$} {:>12} {:>6.1}%  {}",
+            section.name,
+            format_size(section.size, DECIMAL),
+            percent(section.size, report.file_size),
+            section.category,
+            width = width
+        );
+    }
+
+    println!("\nBy category:");
+    for (category, size) in &report.categories {
+        println!(
+            "  {:width$} {:>12} {:>6.1}%",
+            category.to_string(),
+            format_size(*size, DECIMAL),
+            percent(*size, report.file_size),
+            width = width
+        );
+    }
+    println!(
+        "  {:width$} {:>12} {:>6.1}%",
+        "headers and other",
+        format_size(report.other, DECIMAL),
+        percent(report.other, report.file_size),
+        width = width
+    );
+    println!(
+        "  {:width$} {:>12}",
+        "total",
+        format_size(report.file_size, DECIMAL),
+        width = width
+    );
+
+    if report.savings.is_empty() {
+        println!("\nThe binary has no debug info or symbol tables to strip");
+        return;
+    }
+
+    println!("\nPotential savings:");
+    for saving in &report.savings {
+        println!(
+            "  {:30} -{} ({})",
+            saving.setting,
+            format_size(saving.size, DECIMAL),
+            saving.note
+        );
+    }
+}
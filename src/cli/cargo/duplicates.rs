@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use cargo_metadata::Package;
+use clap::Args;
+use hstr::Atom;
+use humansize::{format_size, DECIMAL};
+use rustc_hash::{FxHashMap, FxHashSet};
+use semver::VersionReq;
+
+use super::bin_size::analyze::{self, Analyzer};
+use crate::{
+    cli::util::cargo::to_original_crate_name,
+    package_manager::{cargo::CargoPackageManager, PackageManager, Versions},
+    util::{
+        cargo_build::{
+            run_cargo_metadata_with_deps, CargoBuildTarget, DiagnosticsArgs, DiagnosticsJson,
+        },
+        dep_graph::{DepGraph, Edge},
+        wrap,
+    },
+};
+
+/// List crates which exist in multiple versions in the dependency graph.
+#[derive(Debug, Args)]
+pub(super) struct DuplicatesCommand {
+    /// Maximum number of dependency paths to print for each version.
+    #[clap(long, default_value_t = 3)]
+    max_paths: usize,
+
+    /// Query the crates.io index to find the upgrades which unify the
+    /// duplicates.
+    #[clap(long)]
+    suggest: bool,
+
+    /// Estimate the size saved by unifying the duplicates, by measuring the
+    /// size of each crate in the binary.
+    #[clap(long)]
+    size: bool,
+
+    /// Tool used to measure the size of each crate.
+    #[clap(long, value_enum, default_value = "native", requires = "size")]
+    analyzer: Analyzer,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+impl DuplicatesCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            if self.size {
+                analyze::check_build_target(self.analyzer, &self.build_target)?;
+            }
+
+            let md = run_cargo_metadata_with_deps()?;
+            let graph = DepGraph::new(&md)?;
+
+            let duplicates = find_duplicates(&graph);
+
+            if duplicates.is_empty() {
+                println!("No duplicate crates found");
+                return Ok(());
+            }
+
+            let sizes = if self.size {
+                let json_output = self.diagnostics.open().await?;
+                crate_sizes(self.analyzer, &self.build_target, json_output.as_ref()).await?
+            } else {
+                Default::default()
+            };
+
+            let pm = CargoPackageManager::default();
+            let mut index_cache = FxHashMap::default();
+            let mut total_saving = 0;
+
+            for (name, versions) in &duplicates {
+                print!(
+                    "{} ({})",
+                    name,
+                    versions
+                        .iter()
+                        .map(|p| format!("v{}", p.version))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+
+                // `cargo bloat` does not distinguish the versions of a crate, so we assume
+                // that each version contributes equally.
+                if let Some(size) = sizes.get(&Atom::from(*name)) {
+                    let saving = size * (versions.len() as u64 - 1) / versions.len() as u64;
+                    total_saving += saving;
+
+                    print!(" ~{} may be saved", format_size(saving, DECIMAL));
+                }
+                println!();
+
+                for pkg in versions {
+                    println!("  v{}", pkg.version);
+
+                    let paths = graph.paths_to(&pkg.id, self.max_paths + 1);
+                    for path in paths.iter().take(self.max_paths) {
+                        println!("    {}", format_path(&graph, path));
+                    }
+                    if paths.len() > self.max_paths {
+                        println!("    ...");
+                    }
+                }
+
+                if self.suggest {
+                    let suggestions = suggest(&graph, &pm, &mut index_cache, versions)
+                        .await
+                        .with_context(|| format!("failed to find suggestions for `{}`", name))?;
+
+                    if !suggestions.is_empty() {
+                        println!("  suggestions:");
+                        for s in suggestions {
+                            println!("    - {}", s);
+                        }
+                    }
+                }
+
+                println!();
+            }
+
+            println!("Found {} crates with multiple versions", duplicates.len());
+            if self.size {
+                println!(
+                    "Estimated size saving: {}",
+                    format_size(total_saving, DECIMAL)
+                );
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to find duplicate crates")
+    }
+}
+
+/// Packages grouped by name, sorted by version.
+fn find_duplicates<'a>(graph: &DepGraph<'a>) -> BTreeMap<&'a str, Vec<&'a Package>> {
+    let mut by_name = BTreeMap::<_, Vec<_>>::new();
+
+    for pkg in graph.packages() {
+        by_name.entry(&*pkg.name).or_default().push(pkg);
+    }
+
+    by_name.retain(|_, versions| versions.len() > 1);
+
+    for versions in by_name.values_mut() {
+        versions.sort_by(|a, b| a.version.cmp(&b.version));
+    }
+
+    by_name
+}
+
+fn format_path(graph: &DepGraph, path: &[Edge]) -> String {
+    let Some(first) = path.first() else {
+        return String::new();
+    };
+
+    let mut s = format_package(graph.package(first.from));
+    for edge in path {
+        s.push_str(" -> ");
+        s.push_str(&format_package(graph.package(edge.to)));
+    }
+
+    s
+}
+
+fn format_package(pkg: &Package) -> String {
+    format!("{} v{}", pkg.name, pkg.version)
+}
+
+/// Finds the dependents of the outdated versions, and the upgrades which make
+/// them depend on the latest version.
+async fn suggest(
+    graph: &DepGraph<'_>,
+    pm: &dyn PackageManager,
+    index_cache: &mut FxHashMap<String, Versions>,
+    versions: &[&Package],
+) -> Result<Vec<String>> {
+    let latest = versions.last().unwrap();
+    let mut done = FxHashSet::default();
+    let mut suggestions = vec![];
+
+    for pkg in &versions[..versions.len() - 1] {
+        for edge in graph.dependents(&pkg.id) {
+            if !done.insert(edge.from) {
+                continue;
+            }
+
+            let parent = graph.package(edge.from);
+
+            if graph.is_workspace_member(edge.from) {
+                let req = parent
+                    .dependencies
+                    .iter()
+                    .find(|d| d.name == pkg.name && d.req.matches(&pkg.version))
+                    .map(|d| d.req.to_string())
+                    .unwrap_or_else(|| "?".into());
+
+                suggestions.push(format!(
+                    "raise the requirement of `{}` on `{}` from `{}` to `{}`",
+                    parent.name, pkg.name, req, latest.version
+                ));
+                continue;
+            }
+
+            if !parent.source.as_ref().is_some_and(|s| s.is_crates_io()) {
+                suggestions.push(format!(
+                    "`{}` is not from crates.io, so it should be updated manually",
+                    format_package(parent)
+                ));
+                continue;
+            }
+
+            let parent_versions = match index_cache.get(&parent.name) {
+                Some(v) => v.clone(),
+                None => {
+                    let v = pm.resolve(&parent.name, &VersionReq::STAR).await?;
+                    index_cache.insert(parent.name.clone(), v.clone());
+                    v
+                }
+            };
+
+            // The index is sorted in descending order, and we want the smallest upgrade.
+            let candidate = parent_versions.iter().rev().find(|v| {
+                v.version > parent.version
+                    && v.deps
+                        .iter()
+                        .any(|d| *d.name == *pkg.name && d.constraints.matches(&latest.version))
+            });
+
+            match candidate {
+                Some(candidate) => {
+                    let compatible = VersionReq::parse(&format!("^{}", parent.version))
+                        .is_ok_and(|req| req.matches(&candidate.version));
+
+                    if compatible {
+                        suggestions.push(format!(
+                            "run `cargo update -p {}@{} --precise {}`",
+                            parent.name, parent.version, candidate.version
+                        ));
+                    } else {
+                        suggestions.push(format!(
+                            "upgrade `{}` from v{} to v{} (semver-incompatible)",
+                            parent.name, parent.version, candidate.version
+                        ));
+                    }
+                }
+                None => {
+                    suggestions.push(format!(
+                        "no release of `{}` depends on `{}` v{} yet",
+                        parent.name, pkg.name, latest.version
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(suggestions)
+}
+
+/// Size of each crate in the binary, keyed by the package name.
+async fn crate_sizes(
+    analyzer: Analyzer,
+    build_target: &CargoBuildTarget,
+    json_output: Option<&DiagnosticsJson>,
+) -> Result<FxHashMap<Atom, u64>> {
+    let crates = analyze::crate_sizes(analyzer, build_target, None, json_output).await?;
+
+    let mut sizes = FxHashMap::<_, u64>::default();
+    for crate_ in crates {
+        let Ok(name) = to_original_crate_name(crate_.name) else {
+            continue;
+        };
+
+        *sizes.entry(name).or_default() += crate_.size;
+    }
+
+    Ok(sizes)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        package_manager::{Dependency, PackageVersion},
+        util::dep_graph::fixture::{id, workspace},
+    };
+
+    /// An index with fixed versions, sorted in descending order like
+    /// crates.io.
+    struct FakeIndex(Vec<PackageVersion>);
+
+    #[async_trait]
+    impl PackageManager for FakeIndex {
+        async fn resolve(&self, package_name: &str, _: &VersionReq) -> Result<Versions> {
+            let mut versions = self
+                .0
+                .iter()
+                .filter(|v| *v.name == *package_name)
+                .cloned()
+                .collect::<Vec<_>>();
+            versions.sort_by(|a, b| b.version.cmp(&a.version));
+            Ok(Arc::new(versions))
+        }
+    }
+
+    fn version(name: &str, version: &str, deps: &[(&str, &str)]) -> PackageVersion {
+        PackageVersion {
+            name: name.into(),
+            version: version.parse().unwrap(),
+            deps: deps
+                .iter()
+                .map(|(name, req)| Dependency {
+                    name: (*name).into(),
+                    constraints: req.parse().unwrap(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn find_duplicate_versions() {
+        let md = workspace();
+        let graph = DepGraph::new(&md).unwrap();
+
+        let duplicates = find_duplicates(&graph);
+        assert_eq!(duplicates.keys().copied().collect::<Vec<_>>(), ["c"]);
+        assert_eq!(
+            duplicates["c"]
+                .iter()
+                .map(|p| p.version.to_string())
+                .collect::<Vec<_>>(),
+            ["1.0.0", "2.0.0"]
+        );
+
+        let paths = graph.paths_to(id(&md, "c@2.0.0"), 1);
+        assert_eq!(
+            format_path(&graph, &paths[0]),
+            "app v0.1.0 -> b v1.0.0 -> c v2.0.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn suggest_upgrades() {
+        let md = workspace();
+        let graph = DepGraph::new(&md).unwrap();
+        let duplicates = find_duplicates(&graph);
+
+        let index = FakeIndex(vec![
+            version("a", "1.0.0", &[("c", "^1")]),
+            version("a", "1.1.0", &[("c", "^2")]),
+            version("a", "2.0.0", &[("c", "^2")]),
+        ]);
+
+        let mut suggestions = suggest(&graph, &index, &mut Default::default(), &duplicates["c"])
+            .await
+            .unwrap();
+        suggestions.sort();
+
+        assert_eq!(
+            suggestions,
+            [
+                "`b v1.0.0` is not from crates.io, so it should be updated manually",
+                "raise the requirement of `core` on `c` from `^1.0.0` to `2.0.0`",
+                // The smallest upgrade, which is compatible.
+                "run `cargo update -p a@1.0.0 --precise 1.1.0`",
+            ]
+        );
+
+        let index = FakeIndex(vec![version("a", "1.0.0", &[("c", "^1")])]);
+        let suggestions = suggest(&graph, &index, &mut Default::default(), &duplicates["c"])
+            .await
+            .unwrap();
+        assert!(suggestions.contains(&"no release of `a` depends on `c` v2.0.0 yet".to_string()));
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+use cargo_metadata::{DependencyKind, Metadata, Package};
+use clap::{Args, ValueEnum};
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+
+use self::spdx::Expr;
+use crate::util::{
+    cargo_build::{host_triple, run_cargo_metadata_with_deps_for, CargoBuildTarget},
+    config::load_config_section,
+    dep_graph::DepGraph,
+    wrap,
+};
+
+mod spdx;
+
+/// Collect the licenses of the dependencies and check them against the
+/// `[licenses]` policy in `ddt.toml`.
+///
+/// Only the dependencies of the selected targets are included.
+#[derive(Debug, Args)]
+pub(super) struct LicensesCommand {
+    /// The output format. `notices` includes the license texts, for
+    /// distributing with the binary.
+    #[clap(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    /// Write the output to a file instead of stdout.
+    #[clap(long, short = 'o')]
+    output_path: Option<PathBuf>,
+
+    /// Include build dependencies, which are not shipped with the binary.
+    #[clap(long)]
+    include_build_deps: bool,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// A third-party notices file with the license texts.
+    Notices,
+}
+
+/// `[licenses]` in `ddt.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LicensePolicy {
+    /// Allowed licenses. If empty, all licenses except `deny` are allowed.
+    #[serde(default)]
+    allow: Vec<String>,
+
+    /// Denied licenses, even if they are in `allow`.
+    #[serde(default)]
+    deny: Vec<String>,
+
+    /// Crates which are not checked.
+    #[serde(default)]
+    ignore: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CrateLicense {
+    name: String,
+    version: String,
+    license: Option<String>,
+    license_file: Option<String>,
+    repository: Option<String>,
+    violation: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    crates: Vec<CrateLicense>,
+    /// License expression to `name@version`s.
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl LicensesCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            let policy: LicensePolicy = load_config_section("licenses")?;
+
+            // Only the dependencies for the target platform are shipped.
+            let triple = match self.build_target.target_triple()? {
+                Some(triple) => triple,
+                None => host_triple()?,
+            };
+            let md =
+                run_cargo_metadata_with_deps_for(self.build_target.feature_args(), Some(triple))?;
+            let graph = DepGraph::new(&md)?;
+
+            let packages =
+                collect_packages(&md, &graph, &self.build_target, self.include_build_deps)?;
+
+            let accept = |id: &str| {
+                !policy.deny.iter().any(|l| l == id)
+                    && (policy.allow.is_empty() || policy.allow.iter().any(|l| l == id))
+            };
+
+            let crates = packages
+                .iter()
+                .map(|pkg| CrateLicense {
+                    name: pkg.name.clone(),
+                    version: pkg.version.to_string(),
+                    license: pkg.license.clone(),
+                    license_file: pkg.license_file.as_ref().map(|p| p.to_string()),
+                    repository: pkg.repository.clone(),
+                    violation: if policy.ignore.contains(&pkg.name) {
+                        None
+                    } else {
+                        check_license(pkg, &accept)
+                    },
+                })
+                .collect::<Vec<_>>();
+
+            let mut groups = BTreeMap::<_, Vec<_>>::new();
+            for c in &crates {
+                let key = match (&c.license, &c.license_file) {
+                    (Some(license), _) => license.clone(),
+                    (None, Some(_)) => "(license file)".into(),
+                    (None, None) => "(unknown)".into(),
+                };
+
+                groups
+                    .entry(key)
+                    .or_default()
+                    .push(format!("{}@{}", c.name, c.version));
+            }
+
+            let violations = crates.iter().filter(|c| c.violation.is_some()).count();
+
+            let report = Report { crates, groups };
+
+            let output = match self.format {
+                OutputFormat::Text => render_text(&report),
+                OutputFormat::Json => serde_json::to_string_pretty(&report)
+                    .context("failed to serialize the report")?,
+                OutputFormat::Notices => render_notices(&packages),
+            };
+
+            match &self.output_path {
+                Some(path) => std::fs::write(path, output)
+                    .with_context(|| format!("failed to write {}", path.display()))?,
+                None => print!("{}", output),
+            }
+
+            if violations > 0 {
+                bail!("{} crates violate the license policy", violations)
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to collect licenses")
+    }
+}
+
+/// Collects the non-workspace packages reachable from the selected targets.
+fn collect_packages<'a>(
+    md: &'a Metadata,
+    graph: &DepGraph<'a>,
+    build_target: &CargoBuildTarget,
+    include_build_deps: bool,
+) -> Result<Vec<&'a Package>> {
+    let members = md.workspace_packages();
+
+    let roots = if !build_target.packages.is_empty() {
+        let roots = members
+            .iter()
+            .filter(|p| build_target.packages.contains(&p.name))
+            .collect::<Vec<_>>();
+
+        if roots.len() != build_target.packages.len() {
+            bail!(
+                "failed to find some of the packages {:?} in the workspace",
+                build_target.packages
+            )
+        }
+
+        roots
+    } else if let Some(target) = [
+        &build_target.bin,
+        &build_target.test,
+        &build_target.bench,
+        &build_target.example,
+    ]
+    .into_iter()
+    .find_map(|t| t.as_deref())
+    {
+        let roots = members
+            .iter()
+            .filter(|p| p.targets.iter().any(|t| t.name == target))
+            .collect::<Vec<_>>();
+
+        if roots.is_empty() {
+            bail!("failed to find the target `{}` in the workspace", target)
+        }
+
+        roots
+    } else {
+        members.iter().collect()
+    };
+
+    let include_dev_deps = build_target.tests
+        || build_target.test.is_some()
+        || build_target.benches
+        || build_target.bench.is_some()
+        || build_target.examples
+        || build_target.example.is_some();
+
+    let mut visited = FxHashSet::default();
+    let mut queue = roots.iter().map(|p| (&p.id, true)).collect::<VecDeque<_>>();
+
+    while let Some((id, is_root)) = queue.pop_front() {
+        if !visited.insert(id) {
+            continue;
+        }
+
+        for edge in graph.edges_from(id) {
+            let accepted = edge.dep.dep_kinds.is_empty()
+                || edge.dep.dep_kinds.iter().any(|k| match k.kind {
+                    DependencyKind::Normal => true,
+                    DependencyKind::Build => include_build_deps,
+                    DependencyKind::Development => is_root && include_dev_deps,
+                    DependencyKind::Unknown => false,
+                });
+
+            if accepted {
+                queue.push_back((edge.to, false));
+            }
+        }
+    }
+
+    let mut packages = visited
+        .into_iter()
+        .filter(|id| !graph.is_workspace_member(id))
+        .map(|id| graph.package(id))
+        .collect::<Vec<_>>();
+    packages.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+
+    Ok(packages)
+}
+
+/// Returns the reason if the license of `pkg` is not allowed.
+fn check_license(pkg: &Package, accept: &dyn Fn(&str) -> bool) -> Option<String> {
+    let Some(license) = &pkg.license else {
+        return Some(match &pkg.license_file {
+            Some(file) => format!("only has a license file (`{}`), which needs a review", file),
+            None => "has no license".into(),
+        });
+    };
+
+    match Expr::parse(license) {
+        Ok(expr) => {
+            if expr.is_satisfied_by(accept) {
+                None
+            } else {
+                Some(format!("`{}` is not allowed by the policy", license))
+            }
+        }
+        Err(err) => Some(format!(
+            "has an invalid license expression `{}`: {}",
+            license, err
+        )),
+    }
+}
+
+fn render_text(report: &Report) -> String {
+    let mut s = String::new();
+
+    for (license, crates) in &report.groups {
+        let _ = writeln!(s, "{} ({})", license, crates.len());
+        for c in crates {
+            let _ = writeln!(s, "  {}", c);
+        }
+    }
+
+    let violations = report
+        .crates
+        .iter()
+        .filter_map(|c| Some((c, c.violation.as_ref()?)))
+        .collect::<Vec<_>>();
+
+    if !violations.is_empty() {
+        let _ = writeln!(s, "\nPolicy violations:");
+        for (c, violation) in violations {
+            let _ = writeln!(s, "  {}@{} {}", c.name, c.version, violation);
+        }
+    }
+
+    s
+}
+
+fn render_notices(packages: &[&Package]) -> String {
+    let mut s = String::new();
+
+    let _ = writeln!(s, "THIRD-PARTY SOFTWARE NOTICES");
+    let _ = writeln!(s);
+    let _ = writeln!(
+        s,
+        "This file lists the third-party crates included in this software and their licenses."
+    );
+
+    for pkg in packages {
+        let _ = writeln!(s, "\n{}", "=".repeat(80));
+        let _ = writeln!(s, "{} {}", pkg.name, pkg.version);
+        let _ = writeln!(
+            s,
+            "License: {}",
+            pkg.license.as_deref().unwrap_or("(see below)")
+        );
+        if let Some(repository) = &pkg.repository {
+            let _ = writeln!(s, "Repository: {}", repository);
+        }
+
+        let texts = license_texts(pkg);
+        if texts.is_empty() {
+            let _ = writeln!(s, "{}", "-".repeat(80));
+            let _ = writeln!(s, "(The license text is not included in the package)");
+        }
+
+        for text in texts {
+            let _ = writeln!(s, "{}", "-".repeat(80));
+            let _ = writeln!(s, "{}", text.trim_end());
+        }
+    }
+
+    s
+}
+
+/// Reads `license-file` of the package, or the license files in the package
+/// root.
+fn license_texts(pkg: &Package) -> Vec<String> {
+    if let Some(file) = pkg.license_file() {
+        return std::fs::read_to_string(file).into_iter().collect();
+    }
+
+    let Some(dir) = pkg.manifest_path.parent() else {
+        return vec![];
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut files = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .map(|s| s.to_string_lossy().to_ascii_uppercase())
+                .unwrap_or_default();
+
+            path.is_file()
+                && ["LICENSE", "LICENCE", "COPYING", "NOTICE"]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+
+    files
+        .into_iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .collect()
+}
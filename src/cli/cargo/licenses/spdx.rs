@@ -0,0 +1,167 @@
+//! Minimal parser for SPDX license expressions.
+
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Expr {
+    License(String),
+    /// `license WITH exception`
+    With(String, String),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parses an SPDX expression.
+    ///
+    /// The legacy `MIT/Apache-2.0` syntax, which is still used by many crates,
+    /// is treated as `OR`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s
+            .replace('/', " OR ")
+            .replace('(', " ( ")
+            .replace(')', " ) ");
+        let tokens = s.split_whitespace().collect::<Vec<_>>();
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+
+        if parser.pos != parser.tokens.len() {
+            bail!("unexpected token `{}`", parser.tokens[parser.pos])
+        }
+
+        Ok(expr)
+    }
+
+    /// Returns true if the expression can be satisfied using the licenses
+    /// accepted by `accept`.
+    pub fn is_satisfied_by(&self, accept: &dyn Fn(&str) -> bool) -> bool {
+        match self {
+            Expr::License(id) => accept(id),
+            Expr::With(id, exception) => {
+                accept(&format!("{} WITH {}", id, exception)) || accept(id)
+            }
+            Expr::And(l, r) => l.is_satisfied_by(accept) && r.is_satisfied_by(accept),
+            Expr::Or(l, r) => l.is_satisfied_by(accept) || r.is_satisfied_by(accept),
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&str> {
+        let Some(token) = self.tokens.get(self.pos).copied() else {
+            bail!("unexpected end of the expression")
+        };
+        self.pos += 1;
+
+        Ok(token)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+
+        while self.peek().is_some_and(|t| t.eq_ignore_ascii_case("OR")) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_with()?;
+
+        while self.peek().is_some_and(|t| t.eq_ignore_ascii_case("AND")) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_with()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_with(&mut self) -> Result<Expr> {
+        match self.next()? {
+            "(" => {
+                let expr = self.parse_or()?;
+                if self.next()? != ")" {
+                    bail!("expected `)`")
+                }
+
+                Ok(expr)
+            }
+            ")" => bail!("unexpected `)`"),
+            id => {
+                let id = id.to_string();
+
+                if self.peek().is_some_and(|t| t.eq_ignore_ascii_case("WITH")) {
+                    self.pos += 1;
+                    let exception = self.next()?.to_string();
+
+                    return Ok(Expr::With(id, exception));
+                }
+
+                Ok(Expr::License(id))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn license(id: &str) -> Box<Expr> {
+        Box::new(Expr::License(id.into()))
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            Expr::parse("MIT OR Apache-2.0 AND Zlib").unwrap(),
+            Expr::Or(
+                license("MIT"),
+                Box::new(Expr::And(license("Apache-2.0"), license("Zlib")))
+            )
+        );
+        assert_eq!(
+            Expr::parse("(MIT OR Apache-2.0) AND Zlib").unwrap(),
+            Expr::And(
+                Box::new(Expr::Or(license("MIT"), license("Apache-2.0"))),
+                license("Zlib")
+            )
+        );
+    }
+
+    #[test]
+    fn parse_legacy_slash() {
+        assert_eq!(
+            Expr::parse("MIT/Apache-2.0").unwrap(),
+            Expr::Or(license("MIT"), license("Apache-2.0"))
+        );
+    }
+
+    #[test]
+    fn satisfied_by() {
+        let accept = |id: &str| id == "MIT" || id == "Apache-2.0 WITH LLVM-exception";
+
+        assert!(Expr::parse("MIT OR GPL-3.0")
+            .unwrap()
+            .is_satisfied_by(&accept));
+        assert!(!Expr::parse("MIT AND GPL-3.0")
+            .unwrap()
+            .is_satisfied_by(&accept));
+        assert!(Expr::parse("Apache-2.0 WITH LLVM-exception")
+            .unwrap()
+            .is_satisfied_by(&accept));
+        assert!(Expr::parse("(a OR b").is_err());
+    }
+}
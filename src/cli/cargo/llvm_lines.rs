@@ -0,0 +1,399 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use tempfile::TempDir;
+use tracing::info;
+
+use super::bin_size::analyze::symbol_crate;
+use crate::util::{
+    cancellable,
+    cargo_build::{
+        compile_with, run_cargo_metadata_no_deps, run_cargo_metadata_no_deps_for, CargoBuildTarget,
+        DiagnosticsArgs,
+    },
+    wrap,
+};
+
+/// Count the lines of LLVM IR generated for each generic function, to find
+/// the functions which are instantiated the most.
+///
+/// Only the IR of the selected target is analyzed, but it includes the
+/// instances of generic functions from other crates.
+#[derive(Debug, Args)]
+pub(super) struct LlvmLinesCommand {
+    /// The order of the crates and functions. `lines` and `copies` sort them
+    /// by the most lines or instances first.
+    #[clap(long, value_enum, default_value = "lines")]
+    sort: SortKey,
+
+    /// Only show functions whose name matches this regex.
+    #[clap(long)]
+    filter: Option<Regex>,
+
+    /// Only show functions from these crates.
+    #[clap(long = "crate")]
+    crates: Vec<String>,
+
+    /// Show each instance of generic functions separately, instead of
+    /// grouping them by the generic path.
+    #[clap(long)]
+    no_group: bool,
+
+    /// Maximum number of functions to print. The crates are always all
+    /// printed.
+    #[clap(long, default_value_t = 50)]
+    max_functions: usize,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SortKey {
+    Lines,
+    Copies,
+    Name,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    lines: usize,
+    copies: usize,
+}
+
+impl LlvmLinesCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            let dir = TempDir::new().context("failed to create a directory for the LLVM IR")?;
+            let ll = self.build(dir.path()).await?;
+
+            let content = std::fs::read_to_string(&ll)
+                .with_context(|| format!("failed to read {}", ll.display()))?;
+
+            let mut functions = FxHashMap::<String, Stats>::default();
+            let mut crates = FxHashMap::<String, Stats>::default();
+
+            for (symbol, lines) in parse_ll(&content) {
+                let name = match rustc_demangle::try_demangle(symbol) {
+                    Ok(demangled) => format!("{:#}", demangled),
+                    Err(_) => symbol.to_string(),
+                };
+                let crate_name = symbol_crate(&name).unwrap_or("[Unknown]").to_string();
+
+                if !self.crates.is_empty() && !self.crates.contains(&crate_name) {
+                    continue;
+                }
+
+                let name = if self.no_group {
+                    name
+                } else {
+                    generic_path(&name)
+                };
+
+                if let Some(filter) = &self.filter {
+                    if !filter.is_match(&name) {
+                        continue;
+                    }
+                }
+
+                let stats = functions.entry(name).or_default();
+                stats.lines += lines;
+                stats.copies += 1;
+
+                let stats = crates.entry(crate_name).or_default();
+                stats.lines += lines;
+                stats.copies += 1;
+            }
+
+            let total = Stats {
+                lines: functions.values().map(|s| s.lines).sum(),
+                copies: functions.values().map(|s| s.copies).sum(),
+            };
+
+            println!("Crates:");
+            print_table(self.sort_entries(crates), &total, usize::MAX);
+
+            println!("\nFunctions:");
+            print_table(self.sort_entries(functions), &total, self.max_functions);
+
+            Ok(())
+        })
+        .await
+        .context("failed to count the lines of LLVM IR")
+    }
+
+    /// Builds the target with `--emit=llvm-ir`, writing the IR to a new file
+    /// in `dir`, and returns the path to the `.ll` file.
+    async fn build(&self, dir: &Path) -> Result<PathBuf> {
+        // A separate target directory, so the build cache is not invalidated.
+        let mut bt = self
+            .build_target
+            .with_target_dir(self.build_target.target_dir()?.join("ddt-llvm-lines"));
+
+        // `cargo rustc` passes the flags to a single target, so it fails if no
+        // target is selected and the package has multiple targets.
+        if !bt.lib
+            && [&bt.bin, &bt.bench, &bt.test, &bt.example]
+                .iter()
+                .all(|t| t.is_none())
+        {
+            select_default_target(&mut bt)?;
+        }
+
+        // The path is new for each run, so cargo does not skip the build as
+        // fresh.
+        let path = dir.join("output.ll");
+        let mut emit = OsString::from("--emit=llvm-ir=");
+        emit.push(&path);
+
+        let json_output = self.diagnostics.open().await?;
+        cancellable(async {
+            // rustc writes a file for each codegen unit, and ignores the path
+            // if there are multiple.
+            compile_with(
+                &bt,
+                None,
+                &[],
+                json_output.as_ref(),
+                &[emit, "-Ccodegen-units=1".into()],
+            )
+            .await
+            .context("failed to build with --emit=llvm-ir")
+        })
+        .await?;
+
+        if !path.is_file() {
+            bail!("rustc did not write the LLVM IR to {}", path.display())
+        }
+
+        Ok(path)
+    }
+
+    fn sort_entries(&self, map: FxHashMap<String, Stats>) -> Vec<(String, Stats)> {
+        let mut entries = map.into_iter().collect::<Vec<_>>();
+
+        match self.sort {
+            SortKey::Lines => entries.sort_by(|a, b| {
+                b.1.lines
+                    .cmp(&a.1.lines)
+                    .then_with(|| b.1.copies.cmp(&a.1.copies))
+                    .then_with(|| a.0.cmp(&b.0))
+            }),
+            SortKey::Copies => entries.sort_by(|a, b| {
+                b.1.copies
+                    .cmp(&a.1.copies)
+                    .then_with(|| b.1.lines.cmp(&a.1.lines))
+                    .then_with(|| a.0.cmp(&b.0))
+            }),
+            SortKey::Name => entries.sort_by(|a, b| a.0.cmp(&b.0)),
+        }
+
+        entries
+    }
+}
+
+/// Selects the library of the package, or its only binary.
+fn select_default_target(bt: &mut CargoBuildTarget) -> Result<()> {
+    let md = match &bt.manifest_path {
+        Some(manifest_path) => run_cargo_metadata_no_deps_for(manifest_path.clone())?,
+        None => run_cargo_metadata_no_deps()?,
+    };
+
+    let pkg = match &bt.packages[..] {
+        [] => md
+            .root_package()
+            .context("failed to find the package to analyze. Select a package with `--package`")?,
+        [name] => md
+            .packages
+            .iter()
+            .find(|p| p.name == *name)
+            .with_context(|| format!("failed to find the package `{}`", name))?,
+        _ => bail!("only a single package can be analyzed"),
+    };
+
+    if pkg.targets.iter().any(|t| {
+        t.kind.iter().any(|k| {
+            matches!(
+                k.as_str(),
+                "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro"
+            )
+        })
+    }) {
+        info!(
+            "Analyzing the library of `{}`. Use `--bin` to analyze a binary",
+            pkg.name
+        );
+        bt.lib = true;
+        return Ok(());
+    }
+
+    let bins = pkg
+        .targets
+        .iter()
+        .filter(|t| t.kind.iter().any(|k| k == "bin"))
+        .collect::<Vec<_>>();
+    match &bins[..] {
+        [bin] => {
+            bt.bin = Some(bin.name.clone());
+            Ok(())
+        }
+        [] => bail!("`{}` has no library or binary to analyze", pkg.name),
+        _ => bail!(
+            "`{}` has multiple binaries. Select one with `--bin`: {}",
+            pkg.name,
+            bins.iter().map(|t| &*t.name).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+fn percent(n: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    n as f64 * 100.0 / total as f64
+}
+
+fn print_table(entries: Vec<(String, Stats)>, total: &Stats, max: usize) {
+    println!(
+        "  {:>8} {:>7}  {:>6} {:>7}  name",
+        "lines", "", "copies", ""
+    );
+    println!(
+        "  {:>8} {:>6.1}%  {:>6} {:>6.1}%  (total)",
+        total.lines, 100.0, total.copies, 100.0
+    );
+
+    let len = entries.len();
+    for (name, stats) in entries.into_iter().take(max) {
+        println!(
+            "  {:>8} {:>6.1}%  {:>6} {:>6.1}%  {}",
+            stats.lines,
+            percent(stats.lines, total.lines),
+            stats.copies,
+            percent(stats.copies, total.copies),
+            name
+        );
+    }
+
+    if len > max {
+        println!("  ... and {} more", len - max);
+    }
+}
+
+/// Returns the symbol and the number of lines of each function defined in
+/// the LLVM IR.
+fn parse_ll(content: &str) -> Vec<(&str, usize)> {
+    let mut functions = vec![];
+    let mut current = None;
+
+    for line in content.lines() {
+        match current {
+            None => {
+                if let Some(rest) = line.strip_prefix("define ") {
+                    current = function_name(rest).map(|name| (name, 1));
+                }
+            }
+            Some((name, ref mut lines)) => {
+                *lines += 1;
+
+                if line == "}" {
+                    functions.push((name, *lines));
+                    current = None;
+                }
+            }
+        }
+    }
+
+    functions
+}
+
+/// Extracts `foo` from `... @foo(...` or `... @"foo"(...`.
+fn function_name(define: &str) -> Option<&str> {
+    let (_, rest) = define.split_once('@')?;
+
+    match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').map(|(name, _)| name),
+        None => rest.split_once('(').map(|(name, _)| name),
+    }
+}
+
+/// Removes the generic arguments from a demangled path, so the instances of
+/// a generic function are grouped.
+///
+/// `<T as Trait>` at the start is kept, as it's a part of the path.
+fn generic_path(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut depth = 0usize;
+    let mut prev = None;
+
+    for c in name.chars() {
+        match c {
+            '<' if depth > 0 || prev.is_some_and(|p: char| p.is_alphanumeric() || p == '_') => {
+                depth += 1;
+            }
+            '>' if depth > 0 => depth -= 1,
+            _ if depth > 0 => {}
+            _ => out.push(c),
+        }
+        prev = Some(c);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_functions() {
+        let ll = r#"
+define internal void @"_ZN4core3ptr13drop_in_place17h0123456789abcdefE"(ptr %0) {
+start:
+  ret void
+}
+
+declare void @foo()
+
+define hidden void @_ZN2bs4main17h0123456789abcdefE() unnamed_addr #2 {
+start:
+  call void @foo()
+  ret void
+}
+"#;
+
+        assert_eq!(
+            parse_ll(ll),
+            vec![
+                ("_ZN4core3ptr13drop_in_place17h0123456789abcdefE", 4),
+                ("_ZN2bs4main17h0123456789abcdefE", 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_generic_path() {
+        assert_eq!(
+            generic_path("core::ptr::drop_in_place<alloc::vec::Vec<u8>>"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(
+            generic_path("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"),
+            "<alloc::vec::Vec as core::ops::drop::Drop>::drop"
+        );
+        assert_eq!(
+            generic_path("regex_automata::util::pool::inner::Pool<T,F>::get_slow"),
+            "regex_automata::util::pool::inner::Pool::get_slow"
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+
+use crate::{
+    package_manager::{cargo::CargoPackageManager, PackageManager},
+    util::{cargo_build::cargo_workspace_dir, wrap, PrettyCmd},
+};
+
+/// Summarize the changes of `Cargo.lock` between two revisions, as markdown.
+///
+/// Each revision can be a path to a lockfile or a git ref.
+#[derive(Debug, Args)]
+pub(super) struct LockDiffCommand {
+    /// The old revision.
+    old: String,
+
+    /// The new revision. Defaults to `Cargo.lock` in the working tree.
+    new: Option<String>,
+
+    /// Fetch the crates.io index to count the releases skipped by upgrades.
+    #[clap(long)]
+    fetch_releases: bool,
+}
+
+impl LockDiffCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            let new_name = self.new.as_deref().unwrap_or("working tree");
+
+            let old = read_lockfile(&self.old).await?;
+            let new = match &self.new {
+                Some(rev) => read_lockfile(rev).await?,
+                None => {
+                    let path = cargo_workspace_dir()?.join("Cargo.lock");
+                    parse_lockfile(
+                        &tokio::fs::read_to_string(&path)
+                            .await
+                            .with_context(|| format!("failed to read {}", path.display()))?,
+                    )?
+                }
+            };
+
+            let mut diff = LockDiff::new(&old, &new);
+
+            if self.fetch_releases {
+                let pm = CargoPackageManager::default();
+
+                for change in &mut diff.upgraded {
+                    if !change.is_crates_io {
+                        continue;
+                    }
+
+                    let versions = pm
+                        .resolve(&change.name, &VersionReq::STAR)
+                        .await
+                        .with_context(|| {
+                            format!("failed to fetch the versions of `{}`", change.name)
+                        })?;
+
+                    change.skipped_releases = Some(
+                        versions
+                            .iter()
+                            .filter(|v| v.version > change.from && v.version < change.to)
+                            .count(),
+                    );
+                }
+            }
+
+            print!("{}", diff.to_markdown(&self.old, new_name));
+
+            Ok(())
+        })
+        .await
+        .context("failed to diff Cargo.lock")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: Version,
+    source: Option<String>,
+}
+
+/// Package name to the locked versions and their sources.
+///
+/// A version can be locked multiple times with different sources, like from
+/// crates.io and from git.
+type LockedVersions = BTreeMap<String, BTreeSet<LockedEntry>>;
+
+/// A locked version and its source.
+type LockedEntry = (Version, Option<String>);
+
+/// Reads a lockfile from a file if `rev` exists on disk, and from git
+/// otherwise.
+async fn read_lockfile(rev: &str) -> Result<LockedVersions> {
+    let content = if Path::new(rev).is_file() {
+        tokio::fs::read_to_string(rev)
+            .await
+            .with_context(|| format!("failed to read {}", rev))?
+    } else {
+        PrettyCmd::new(format!("Reading Cargo.lock at {}", rev), "git")
+            .dir(cargo_workspace_dir()?)
+            .arg("show")
+            .arg(format!("{}:./Cargo.lock", rev))
+            .output()
+            .await
+            .with_context(|| format!("failed to read Cargo.lock at git ref `{}`", rev))?
+    };
+
+    parse_lockfile(&content).with_context(|| format!("failed to parse Cargo.lock of `{}`", rev))
+}
+
+fn parse_lockfile(content: &str) -> Result<LockedVersions> {
+    let lockfile: Lockfile = toml_edit::de::from_str(content).context("invalid Cargo.lock")?;
+
+    let mut packages = LockedVersions::new();
+    for pkg in lockfile.package {
+        packages
+            .entry(pkg.name)
+            .or_default()
+            .insert((pkg.version, pkg.source));
+    }
+
+    Ok(packages)
+}
+
+#[derive(Debug, Default)]
+struct LockDiff {
+    added: Vec<(String, Version)>,
+    removed: Vec<(String, Version)>,
+    upgraded: Vec<VersionChange>,
+    downgraded: Vec<VersionChange>,
+    source_changes: Vec<SourceChange>,
+    /// Crates which have more versions than before.
+    new_duplicates: Vec<(String, Vec<LockedEntry>)>,
+}
+
+#[derive(Debug)]
+struct VersionChange {
+    name: String,
+    from: Version,
+    to: Version,
+    is_crates_io: bool,
+    skipped_releases: Option<usize>,
+}
+
+#[derive(Debug)]
+struct SourceChange {
+    name: String,
+    version: Version,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Versions in the same line are semver-compatible, like `1.2.0` and `1.3.0`,
+/// or `0.2.0` and `0.2.1`.
+fn compat_line(version: &Version) -> (u64, u64, u64) {
+    match (version.major, version.minor) {
+        (0, 0) => (0, 0, version.patch),
+        (0, minor) => (0, minor, 0),
+        (major, _) => (major, 0, 0),
+    }
+}
+
+impl LockDiff {
+    fn new(old: &LockedVersions, new: &LockedVersions) -> Self {
+        let mut diff = LockDiff::default();
+
+        let names = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+        let empty = BTreeSet::new();
+
+        for name in names {
+            let old_entries = old.get(name).unwrap_or(&empty);
+            let new_entries = new.get(name).unwrap_or(&empty);
+
+            let mut removed = old_entries.difference(new_entries).collect::<Vec<_>>();
+            let mut added = new_entries.difference(old_entries).collect::<Vec<_>>();
+
+            // The same version from another source.
+            removed.retain(|(version, from)| {
+                let Some(i) = added.iter().position(|(v, _)| v == version) else {
+                    return true;
+                };
+                let (_, to) = added.remove(i);
+
+                diff.source_changes.push(SourceChange {
+                    name: name.clone(),
+                    version: version.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+                false
+            });
+
+            // Pair the versions in the same semver-compatible line, so `1.0 + 2.0 -> 1.1 +
+            // 2.1` is reported as two upgrades. A single version on each side is a
+            // breaking upgrade or downgrade.
+            let mut pairs = vec![];
+            removed.retain(|(from, from_source)| {
+                let Some(i) = added
+                    .iter()
+                    .position(|(to, _)| compat_line(to) == compat_line(from))
+                else {
+                    return true;
+                };
+
+                pairs.push(((from, from_source), added.remove(i)));
+                false
+            });
+            if let ([(from, from_source)], [to]) = (&*removed, &*added) {
+                pairs.push(((from, from_source), to));
+                removed.clear();
+                added.clear();
+            }
+
+            for ((from, from_source), (to, to_source)) in pairs {
+                if from_source != to_source {
+                    diff.source_changes.push(SourceChange {
+                        name: name.clone(),
+                        version: to.clone(),
+                        from: from_source.clone(),
+                        to: to_source.clone(),
+                    });
+                }
+
+                let change = VersionChange {
+                    name: name.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                    is_crates_io: to_source.as_deref().is_some_and(is_crates_io),
+                    skipped_releases: None,
+                };
+
+                if to > from {
+                    diff.upgraded.push(change);
+                } else {
+                    diff.downgraded.push(change);
+                }
+            }
+
+            for (v, _) in removed {
+                diff.removed.push((name.clone(), v.clone()));
+            }
+            for (v, _) in added {
+                diff.added.push((name.clone(), v.clone()));
+            }
+
+            if new_entries.len() > 1 && new_entries.len() > old_entries.len() {
+                diff.new_duplicates
+                    .push((name.clone(), new_entries.iter().cloned().collect()));
+            }
+        }
+
+        diff
+    }
+
+    fn to_markdown(&self, old_name: &str, new_name: &str) -> String {
+        let mut s = String::new();
+
+        let _ = writeln!(
+            s,
+            "### `Cargo.lock` changes (`{}` → `{}`)",
+            old_name, new_name
+        );
+        let _ = writeln!(s);
+        let _ = writeln!(
+            s,
+            "{} added, {} removed, {} upgraded, {} downgraded",
+            self.added.len(),
+            self.removed.len(),
+            self.upgraded.len(),
+            self.downgraded.len()
+        );
+
+        if !self.added.is_empty() {
+            let _ = writeln!(s, "\n#### Added\n\n| Crate | Version |\n| --- | --- |");
+            for (name, version) in &self.added {
+                let _ = writeln!(s, "| `{}` | {} |", name, version);
+            }
+        }
+
+        if !self.removed.is_empty() {
+            let _ = writeln!(s, "\n#### Removed\n\n| Crate | Version |\n| --- | --- |");
+            for (name, version) in &self.removed {
+                let _ = writeln!(s, "| `{}` | {} |", name, version);
+            }
+        }
+
+        if !self.upgraded.is_empty() {
+            let with_releases = self.upgraded.iter().any(|c| c.skipped_releases.is_some());
+
+            if with_releases {
+                let _ = writeln!(
+                    s,
+                    "\n#### Upgraded\n\n| Crate | From | To | Skipped releases |\n| --- | --- | \
+                     --- | --- |"
+                );
+            } else {
+                let _ = writeln!(
+                    s,
+                    "\n#### Upgraded\n\n| Crate | From | To |\n| --- | --- | --- |"
+                );
+            }
+
+            for c in &self.upgraded {
+                let _ = write!(s, "| `{}` | {} | {} |", c.name, c.from, c.to);
+                if with_releases {
+                    match c.skipped_releases {
+                        Some(n) => {
+                            let _ = write!(s, " {} |", n);
+                        }
+                        None => {
+                            let _ = write!(s, " - |");
+                        }
+                    }
+                }
+                let _ = writeln!(s);
+            }
+        }
+
+        if !self.downgraded.is_empty() {
+            let _ = writeln!(
+                s,
+                "\n#### Downgraded\n\n| Crate | From | To |\n| --- | --- | --- |"
+            );
+            for c in &self.downgraded {
+                let _ = writeln!(s, "| `{}` | {} | {} |", c.name, c.from, c.to);
+            }
+        }
+
+        if !self.source_changes.is_empty() {
+            let _ = writeln!(
+                s,
+                "\n#### :warning: Source changes\n\n| Crate | Version | From | To |\n| --- | --- \
+                 | --- | --- |"
+            );
+            for c in &self.source_changes {
+                let _ = writeln!(
+                    s,
+                    "| `{}` | {} | {} | {} |",
+                    c.name,
+                    c.version,
+                    describe_source(c.from.as_deref()),
+                    describe_source(c.to.as_deref())
+                );
+            }
+        }
+
+        if !self.new_duplicates.is_empty() {
+            let _ = writeln!(
+                s,
+                "\n#### :warning: New duplicate versions\n\n| Crate | Versions |\n| --- | --- |"
+            );
+            for (name, versions) in &self.new_duplicates {
+                let _ = writeln!(
+                    s,
+                    "| `{}` | {} |",
+                    name,
+                    versions
+                        .iter()
+                        .map(|(version, source)| match source {
+                            Some(s) if is_crates_io(s) => version.to_string(),
+                            _ => format!("{} ({})", version, describe_source(source.as_deref())),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+
+        s
+    }
+}
+
+fn is_crates_io(source: &str) -> bool {
+    source == "registry+https://github.com/rust-lang/crates.io-index"
+        || source == "sparse+https://index.crates.io/"
+}
+
+fn describe_source(source: Option<&str>) -> String {
+    match source {
+        None => "path".into(),
+        Some(s) if is_crates_io(s) => "crates.io".into(),
+        Some(s) => match s.split_once('+') {
+            Some(("git", url)) => format!("git `{}`", url),
+            Some((_, url)) => format!("registry `{}`", url),
+            None => format!("`{}`", s),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+    const GIT: &str = "git+https://github.com/example/serde?rev=abc#abc";
+
+    fn lockfile(packages: &[(&str, &str, Option<&str>)]) -> LockedVersions {
+        let mut content = String::new();
+        for (name, version, source) in packages {
+            let _ = writeln!(
+                content,
+                "[[package]]\nname = \"{}\"\nversion = \"{}\"",
+                name, version
+            );
+            if let Some(source) = source {
+                let _ = writeln!(content, "source = \"{}\"", source);
+            }
+        }
+
+        parse_lockfile(&content).unwrap()
+    }
+
+    fn names(v: &[(String, Version)]) -> Vec<String> {
+        v.iter().map(|(n, v)| format!("{}@{}", n, v)).collect()
+    }
+
+    fn changes(v: &[VersionChange]) -> Vec<String> {
+        v.iter()
+            .map(|c| format!("{}@{}->{}", c.name, c.from, c.to))
+            .collect()
+    }
+
+    #[test]
+    fn version_changes() {
+        let old = lockfile(&[
+            ("app", "0.1.0", None),
+            ("libc", "0.2.150", Some(CRATES_IO)),
+            ("syn", "1.0.109", Some(CRATES_IO)),
+            ("syn", "2.0.10", Some(CRATES_IO)),
+            ("old", "1.0.0", Some(CRATES_IO)),
+            ("log", "0.4.20", Some(CRATES_IO)),
+        ]);
+        let new = lockfile(&[
+            ("app", "0.1.0", None),
+            ("libc", "0.2.155", Some(CRATES_IO)),
+            ("syn", "1.0.109", Some(CRATES_IO)),
+            ("syn", "2.0.60", Some(CRATES_IO)),
+            ("log", "0.4.19", Some(CRATES_IO)),
+            ("new", "0.3.0", Some(CRATES_IO)),
+            ("new", "1.0.0", Some(CRATES_IO)),
+        ]);
+
+        let diff = LockDiff::new(&old, &new);
+
+        assert_eq!(
+            changes(&diff.upgraded),
+            ["libc@0.2.150->0.2.155", "syn@2.0.10->2.0.60"]
+        );
+        assert!(diff.upgraded.iter().all(|c| c.is_crates_io));
+        assert_eq!(changes(&diff.downgraded), ["log@0.4.20->0.4.19"]);
+        assert_eq!(names(&diff.removed), ["old@1.0.0"]);
+        assert_eq!(names(&diff.added), ["new@0.3.0", "new@1.0.0"]);
+        assert!(diff.source_changes.is_empty());
+        assert_eq!(
+            diff.new_duplicates
+                .iter()
+                .map(|(name, _)| &**name)
+                .collect::<Vec<_>>(),
+            ["new"]
+        );
+
+        let old = lockfile(&[
+            ("syn", "1.0.109", Some(CRATES_IO)),
+            ("syn", "2.0.10", Some(CRATES_IO)),
+            ("x", "0.9.0", Some(CRATES_IO)),
+            ("y", "1.0.0", Some(CRATES_IO)),
+        ]);
+        let new = lockfile(&[
+            ("syn", "2.0.60", Some(CRATES_IO)),
+            ("x", "0.3.0", Some(CRATES_IO)),
+            ("x", "1.0.0", Some(CRATES_IO)),
+            ("y", "2.0.0", Some(CRATES_IO)),
+        ]);
+
+        let diff = LockDiff::new(&old, &new);
+
+        // Only the versions in the same semver-compatible line are paired,
+        // unless there's a single version on each side.
+        assert_eq!(
+            changes(&diff.upgraded),
+            ["syn@2.0.10->2.0.60", "y@1.0.0->2.0.0"]
+        );
+        assert!(diff.downgraded.is_empty());
+        assert_eq!(names(&diff.removed), ["syn@1.0.109", "x@0.9.0"]);
+        assert_eq!(names(&diff.added), ["x@0.3.0", "x@1.0.0"]);
+    }
+
+    #[test]
+    fn same_version_from_different_sources() {
+        let old = lockfile(&[("serde", "1.0.200", Some(CRATES_IO))]);
+
+        // Both sources are locked, so neither overwrites the other.
+        let both = lockfile(&[
+            ("serde", "1.0.200", Some(CRATES_IO)),
+            ("serde", "1.0.200", Some(GIT)),
+        ]);
+        assert_eq!(both["serde"].len(), 2);
+
+        let diff = LockDiff::new(&old, &both);
+        assert_eq!(names(&diff.added), ["serde@1.0.200"]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.source_changes.is_empty());
+        assert_eq!(diff.new_duplicates.len(), 1);
+        assert!(diff.to_markdown("old", "new").contains(
+            "| `serde` | 1.0.200 (git `https://github.com/example/serde?rev=abc#abc`), 1.0.200 |"
+        ));
+
+        // Replaced by the git version.
+        let git = lockfile(&[("serde", "1.0.200", Some(GIT))]);
+        let diff = LockDiff::new(&old, &git);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert!(diff.upgraded.is_empty() && diff.downgraded.is_empty());
+        assert_eq!(diff.source_changes.len(), 1);
+        assert_eq!(diff.source_changes[0].from.as_deref(), Some(CRATES_IO));
+        assert_eq!(diff.source_changes[0].to.as_deref(), Some(GIT));
+    }
+}
@@ -0,0 +1,516 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use cargo_metadata::DependencyKind;
+use clap::Args;
+use semver::{Version, VersionReq};
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+use crate::{
+    package_manager::{cargo::CargoPackageManager, PackageManager},
+    util::{
+        cargo_build::{cargo_target_dir, run_cargo_metadata_with_deps_for, FeatureArgs},
+        dep_graph::DepGraph,
+        wrap, PrettyCmd,
+    },
+};
+
+const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// Check if the workspace compiles with the lowest versions allowed by the
+/// requirements of its direct dependencies.
+///
+/// This works on a scratch copy of the workspace, so `Cargo.lock` of the
+/// workspace is not modified. Path dependencies outside of the workspace root
+/// are not copied.
+#[derive(Debug, Args)]
+pub(super) struct CheckMinVersionsCommand {
+    /// Find the lowest compiling version of each failing requirement by
+    /// bisecting the releases.
+    #[clap(long)]
+    bisect: bool,
+
+    /// Only check the requirements of these workspace members.
+    #[clap(long = "package", short = 'p')]
+    packages: Vec<String>,
+
+    #[clap(flatten)]
+    features: FeatureArgs,
+}
+
+/// A requirement of a workspace member on a crates.io package.
+#[derive(Debug)]
+struct Requirement {
+    member: String,
+    name: String,
+    req: VersionReq,
+    locked: Version,
+    /// The lowest version matching `req`.
+    lowest: Version,
+}
+
+/// Requirements of workspace members, which are resolved to the same locked
+/// package.
+#[derive(Debug)]
+struct Pin {
+    name: String,
+    locked: Version,
+    /// The highest of the lowest versions of the requirements.
+    lowest: Version,
+    requirements: Vec<(String, VersionReq)>,
+}
+
+impl CheckMinVersionsCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            // Optional dependencies are in the graph only if they are enabled.
+            let md = run_cargo_metadata_with_deps_for(self.features.clone(), None)?;
+            let graph = DepGraph::new(&md)?;
+            let pm = CargoPackageManager { skip_yanked: true };
+
+            for name in &self.packages {
+                if !md
+                    .workspace_members
+                    .iter()
+                    .any(|id| graph.package(id).name == *name)
+                {
+                    bail!("`{}` is not a member of the workspace", name)
+                }
+            }
+
+            let mut requirements = vec![];
+
+            for member in &md.workspace_members {
+                let pkg = graph.package(member);
+                if !self.packages.is_empty() && !self.packages.contains(&pkg.name) {
+                    continue;
+                }
+
+                for decl in &pkg.dependencies {
+                    if decl.kind == DependencyKind::Development
+                        || decl.path.is_some()
+                        || decl.source.as_deref() != Some(CRATES_IO)
+                    {
+                        continue;
+                    }
+
+                    let Some(locked) = graph
+                        .dependencies(member)
+                        .map(|id| graph.package(id))
+                        .find(|p| p.name == decl.name && decl.req.matches(&p.version))
+                    else {
+                        // Disabled optional dependency, or a dependency for another platform.
+                        continue;
+                    };
+
+                    let versions = pm.resolve(&decl.name, &decl.req).await.with_context(|| {
+                        format!("failed to resolve `{} = \"{}\"`", decl.name, decl.req)
+                    })?;
+                    let Some(lowest) = versions.last() else {
+                        warn!("No release of `{}` matches `{}`", decl.name, decl.req);
+                        continue;
+                    };
+
+                    requirements.push(Requirement {
+                        member: pkg.name.clone(),
+                        name: decl.name.clone(),
+                        req: decl.req.clone(),
+                        locked: locked.version.clone(),
+                        lowest: lowest.version.clone(),
+                    });
+                }
+            }
+
+            let pins = merge_pins(requirements);
+
+            if pins.is_empty() {
+                println!("All dependencies are already locked to their lowest versions");
+                return Ok(());
+            }
+
+            let mut check_args = self.features.cargo_args();
+            if self.packages.is_empty() {
+                check_args.push("--workspace".into());
+            }
+            for name in &self.packages {
+                check_args.push("-p".into());
+                check_args.push(name.clone());
+            }
+
+            let scratch = Scratch::new(
+                md.workspace_root.as_std_path(),
+                &cargo_target_dir()?,
+                check_args,
+            )?;
+
+            let mut conflicts = vec![];
+            let mut applied = vec![];
+            for pin in &pins {
+                match scratch.pin(&pin.name, &pin.locked, &pin.lowest).await {
+                    Ok(()) => applied.push(pin),
+                    Err(err) => {
+                        warn!("Failed to select {} v{}: {:?}", pin.name, pin.lowest, err);
+                        conflicts.push(pin)
+                    }
+                }
+            }
+
+            let combined = scratch.check().await;
+
+            let mut failing = vec![];
+            if combined.is_err() {
+                info!(
+                    "The workspace does not compile with the lowest versions. Checking each \
+                     dependency..."
+                );
+
+                for pin in applied {
+                    scratch.reset()?;
+                    scratch.pin(&pin.name, &pin.locked, &pin.lowest).await?;
+
+                    if scratch.check().await.is_ok() {
+                        continue;
+                    }
+
+                    let lowest_compiling = if self.bisect {
+                        scratch.bisect(&pm, pin).await?
+                    } else {
+                        None
+                    };
+
+                    failing.push((pin, lowest_compiling));
+                }
+            }
+
+            if failing.is_empty() {
+                if let Err(err) = combined {
+                    return Err(err.context(
+                        "the workspace does not compile with the lowest versions, although it \
+                         compiles with each of them alone",
+                    ));
+                }
+
+                println!("The workspace compiles with the lowest versions of the dependencies");
+            } else {
+                println!("Requirements which need raising:");
+
+                for (pin, lowest_compiling) in &failing {
+                    for (member, req) in &pin.requirements {
+                        print!(
+                            "  {}: `{} = \"{}\"` does not compile with v{}",
+                            member, pin.name, req, pin.lowest
+                        );
+
+                        match lowest_compiling {
+                            Some(v) => println!(" (lowest compiling version: v{})", v),
+                            None => println!(),
+                        }
+                    }
+                }
+            }
+
+            if !conflicts.is_empty() {
+                println!("Requirements which could not be lowered because of other requirements:");
+
+                for pin in &conflicts {
+                    for (member, req) in &pin.requirements {
+                        println!("  {}: `{} = \"{}\"`", member, pin.name, req);
+                    }
+                }
+            }
+
+            if !failing.is_empty() {
+                bail!("{} requirements need raising", failing.len());
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to check minimal versions")
+    }
+}
+
+/// A copy of the workspace.
+struct Scratch {
+    dir: TempDir,
+    target_dir: PathBuf,
+    original_lockfile: Option<String>,
+    /// The packages and features for `cargo check`.
+    check_args: Vec<String>,
+}
+
+impl Scratch {
+    fn new(workspace_root: &Path, target_dir: &Path, check_args: Vec<String>) -> Result<Self> {
+        let dir = TempDir::new().context("failed to create a scratch directory")?;
+
+        info!("Copying the workspace to {}", dir.path().display());
+        copy_dir(workspace_root, dir.path(), target_dir).context("failed to copy the workspace")?;
+
+        let original_lockfile = fs::read_to_string(dir.path().join("Cargo.lock")).ok();
+
+        Ok(Self {
+            dir,
+            // Reuse the build cache between runs.
+            target_dir: target_dir.join("ddt-min-versions"),
+            original_lockfile,
+            check_args,
+        })
+    }
+
+    fn reset(&self) -> Result<()> {
+        let path = self.dir.path().join("Cargo.lock");
+
+        match &self.original_lockfile {
+            Some(content) => fs::write(path, content).context("failed to restore Cargo.lock"),
+            None => {
+                let _ = fs::remove_file(path);
+                Ok(())
+            }
+        }
+    }
+
+    fn cargo(&self, description: &str) -> PrettyCmd {
+        let mut cmd = PrettyCmd::new(description, "cargo");
+        cmd.dir(self.dir.path())
+            .env("CARGO_TARGET_DIR", &self.target_dir);
+        cmd
+    }
+
+    async fn pin(&self, name: &str, locked: &Version, version: &Version) -> Result<()> {
+        self.cargo(&format!("Selecting {} v{}", name, version))
+            .arg("update")
+            .arg("-p")
+            .arg(format!("{}@{}", name, locked))
+            .arg("--precise")
+            .arg(version.to_string())
+            .exec()
+            .await
+    }
+
+    /// Checks if the workspace compiles. The error contains the output of
+    /// cargo.
+    async fn check(&self) -> Result<()> {
+        self.cargo("Fetching dependencies")
+            .arg("fetch")
+            .arg("--locked")
+            .exec_captured()
+            .await?;
+
+        let mut cmd = self.cargo("Checking the workspace");
+        cmd.arg("check")
+            .arg("--locked")
+            .arg("--offline")
+            .args(&self.check_args);
+
+        cmd.exec_captured().await
+    }
+
+    /// Finds the lowest version between the lowest allowed version and the
+    /// locked version which compiles.
+    async fn bisect(&self, pm: &dyn PackageManager, pin: &Pin) -> Result<Option<Version>> {
+        let req = VersionReq::parse(&format!(">{}, <={}", pin.lowest, pin.locked))?;
+
+        let mut versions = pm
+            .resolve(&pin.name, &req)
+            .await?
+            .iter()
+            .map(|v| v.version.clone())
+            .collect::<Vec<_>>();
+        // `resolve` returns the newest version first, and the bisection needs
+        // them in ascending order.
+        versions.reverse();
+
+        first_compiling(&versions, |version| async move {
+            self.reset()?;
+            Ok(match self.pin(&pin.name, &pin.locked, version).await {
+                Ok(()) => self.check().await.is_ok(),
+                Err(_) => false,
+            })
+        })
+        .await
+    }
+}
+
+/// Merges the requirements which are resolved to the same package, and drops
+/// the packages which are already locked to their lowest versions.
+fn merge_pins(requirements: Vec<Requirement>) -> Vec<Pin> {
+    let mut pins = BTreeMap::<(String, Version), Pin>::new();
+
+    for r in requirements {
+        let pin = pins
+            .entry((r.name.clone(), r.locked.clone()))
+            .or_insert_with(|| Pin {
+                name: r.name.clone(),
+                locked: r.locked,
+                lowest: r.lowest.clone(),
+                requirements: vec![],
+            });
+
+        if r.lowest > pin.lowest {
+            pin.lowest = r.lowest;
+        }
+        pin.requirements.push((r.member, r.req));
+    }
+
+    pins.into_values()
+        .filter(|pin| pin.lowest != pin.locked)
+        .collect()
+}
+
+/// Finds the first of `versions`, sorted in ascending order, which compiles.
+///
+/// The versions after a compiling version are assumed to compile too.
+async fn first_compiling<'a, F, Fut>(
+    versions: &'a [Version],
+    mut compiles: F,
+) -> Result<Option<Version>>
+where
+    F: FnMut(&'a Version) -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let (mut lo, mut hi) = (0, versions.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+
+        if compiles(&versions[mid]).await? {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Ok(versions.get(lo).cloned())
+}
+
+/// Copies `src` to `dst`, except `.git` and the target directory. Symlinks
+/// are copied as symlinks.
+fn copy_dir(src: &Path, dst: &Path, target_dir: &Path) -> Result<()> {
+    fs::create_dir_all(dst)?;
+
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path == target_dir || entry.file_name() == ".git" {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&path, &dst.join(entry.file_name()), target_dir)?;
+        } else if file_type.is_file() {
+            fs::copy(&path, dst.join(entry.file_name()))
+                .with_context(|| format!("failed to copy {}", path.display()))?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&path, &dst.join(entry.file_name()))
+                .with_context(|| format!("failed to copy the symlink {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a symlink at `dst` with the same target as `src`.
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, _: &Path) -> Result<()> {
+    bail!(
+        "copying symlinks is not supported on this platform: {}",
+        src.display()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn requirement(member: &str, name: &str, req: &str, locked: &str, lowest: &str) -> Requirement {
+        Requirement {
+            member: member.into(),
+            name: name.into(),
+            req: req.parse().unwrap(),
+            locked: locked.parse().unwrap(),
+            lowest: lowest.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn merge_requirements() {
+        let pins = merge_pins(vec![
+            requirement("app", "serde", "^1.0.100", "1.0.200", "1.0.100"),
+            requirement("core", "serde", "^1.0.150", "1.0.200", "1.0.150"),
+            // Already locked to the lowest version.
+            requirement("app", "log", "^0.4.20", "0.4.20", "0.4.20"),
+            // Another locked version of the same crate.
+            requirement("core", "syn", "^1", "1.0.109", "1.0.0"),
+            requirement("app", "syn", "^2", "2.0.60", "2.0.0"),
+        ]);
+
+        let summary = pins
+            .iter()
+            .map(|pin| {
+                format!(
+                    "{} {} -> {} ({})",
+                    pin.name,
+                    pin.locked,
+                    pin.lowest,
+                    pin.requirements
+                        .iter()
+                        .map(|(member, req)| format!("{} {}", member, req))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            [
+                // The highest of the lowest versions can be selected for both.
+                "serde 1.0.200 -> 1.0.150 (app ^1.0.100, core ^1.0.150)",
+                "syn 1.0.109 -> 1.0.0 (core ^1)",
+                "syn 2.0.60 -> 2.0.0 (app ^2)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn bisect_versions() {
+        let versions =
+            ["1.0.0", "1.1.0", "1.2.0", "1.3.0", "1.4.0"].map(|v| v.parse::<Version>().unwrap());
+
+        for first in 0..=versions.len() {
+            let checked = RefCell::new(vec![]);
+
+            let found = first_compiling(&versions, |v| {
+                checked.borrow_mut().push(v.clone());
+                let compiles = versions[first..].contains(v);
+                async move { Ok(compiles) }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(found.as_ref(), versions.get(first));
+            assert!(checked.borrow().len() <= 3);
+        }
+
+        assert!(
+            first_compiling(&versions, |_| async { bail!("failed to pin") })
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,6 +1,17 @@
 mod bin_size;
+mod duplicates;
+mod licenses;
+mod llvm_lines;
+mod lock_diff;
+mod min_versions;
+mod profile_tune;
+mod why;
 
-use self::bin_size::BinSizeCommand;
+use self::{
+    bin_size::BinSizeCommand, duplicates::DuplicatesCommand, licenses::LicensesCommand,
+    llvm_lines::LlvmLinesCommand, lock_diff::LockDiffCommand,
+    min_versions::CheckMinVersionsCommand, profile_tune::ProfileTuneCommand, why::WhyCommand,
+};
 use anyhow::Result;
 use clap::{Args, Subcommand};
 
@@ -15,6 +26,13 @@ impl CargoCommand {
     pub async fn run(self) -> Result<()> {
         match self.cmd {
             Cmd::BinSize(cmd) => cmd.run().await,
+            Cmd::Duplicates(cmd) => cmd.run().await,
+            Cmd::Why(cmd) => cmd.run().await,
+            Cmd::LockDiff(cmd) => cmd.run().await,
+            Cmd::CheckMinVersions(cmd) => cmd.run().await,
+            Cmd::Licenses(cmd) => cmd.run().await,
+            Cmd::ProfileTune(cmd) => cmd.run().await,
+            Cmd::LlvmLines(cmd) => cmd.run().await,
         }
     }
 }
@@ -22,4 +40,11 @@ impl CargoCommand {
 #[derive(Debug, Subcommand)]
 enum Cmd {
     BinSize(BinSizeCommand),
+    Duplicates(DuplicatesCommand),
+    Why(WhyCommand),
+    LockDiff(LockDiffCommand),
+    CheckMinVersions(CheckMinVersionsCommand),
+    Licenses(LicensesCommand),
+    ProfileTune(ProfileTuneCommand),
+    LlvmLines(LlvmLinesCommand),
 }
@@ -0,0 +1,385 @@
+use std::{
+    io::{self, IsTerminal},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use humansize::{format_size, DECIMAL};
+use toml_edit::{table, value, DocumentMut, Item};
+
+use super::bin_size::{
+    analyze::{analyze_build_in, check_build_target, Analyzer},
+    bench::BenchArgs,
+};
+use crate::util::{
+    cargo_build::{cargo_root_manifest, CargoBuildTarget, DiagnosticsArgs},
+    wrap,
+};
+
+/// Profile settings explored by the command, in the order of the columns.
+const KEYS: [&str; 5] = ["lto", "codegen-units", "panic", "strip", "debug"];
+
+/// Build the target with each combination of profile settings, and write the
+/// chosen one to the root `Cargo.toml`.
+///
+/// Each combination is built from scratch in a separate target directory, so
+/// the compile time includes the dependencies.
+#[derive(Debug, Args)]
+pub(super) struct ProfileTuneCommand {
+    /// Comma-separated values of `lto` to try. Each combination of the values
+    /// of `--lto`, `--codegen-units`, `--panic`, `--strip` and `--debug`, which
+    /// take lists the same way, is built.
+    #[clap(long, value_delimiter = ',', default_value = "off,thin,fat")]
+    lto: Vec<String>,
+
+    #[clap(long, value_delimiter = ',', default_value = "16,1")]
+    codegen_units: Vec<String>,
+
+    #[clap(long, value_delimiter = ',', default_value = "unwind,abort")]
+    panic: Vec<String>,
+
+    #[clap(long, value_delimiter = ',', default_value = "none,symbols")]
+    strip: Vec<String>,
+
+    #[clap(long, value_delimiter = ',', default_value = "0")]
+    debug: Vec<String>,
+
+    /// Maximum number of configurations to build without asking. Larger
+    /// matrices must be confirmed, and fail if the terminal is not
+    /// interactive.
+    #[clap(long, default_value_t = 32)]
+    max_builds: usize,
+
+    /// Index of the configuration to write, instead of prompting.
+    #[clap(long)]
+    choose: Option<usize>,
+
+    #[clap(flatten)]
+    bench: BenchArgs,
+
+    #[clap(flatten)]
+    build_target: CargoBuildTarget,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+}
+
+#[derive(Debug)]
+struct Measurement {
+    /// Values of [KEYS].
+    settings: Vec<String>,
+    size: u64,
+    compile_time: Duration,
+    runtime: Option<Duration>,
+    pareto: bool,
+}
+
+impl Measurement {
+    fn costs(&self) -> [u128; 3] {
+        [
+            self.size as u128,
+            self.compile_time.as_nanos(),
+            self.runtime.map_or(0, |r| r.as_nanos()),
+        ]
+    }
+
+    /// Returns true if `self` is at least as good as `other` in every cost, and
+    /// better in at least one.
+    fn dominates(&self, other: &Measurement) -> bool {
+        let (a, b) = (self.costs(), other.costs());
+
+        a.iter().zip(&b).all(|(a, b)| a <= b) && a.iter().zip(&b).any(|(a, b)| a < b)
+    }
+}
+
+impl ProfileTuneCommand {
+    pub async fn run(self) -> Result<()> {
+        wrap(async move {
+            check_build_target(Analyzer::Native, &self.build_target)?;
+            let json_output = self.diagnostics.open().await?;
+
+            let profile_name = self.build_target.profile_name().to_string();
+            let env_prefix = format!(
+                "CARGO_PROFILE_{}_",
+                profile_name.to_ascii_uppercase().replace('-', "_")
+            );
+
+            let mut matrix = vec![vec![]];
+            for values in [
+                &self.lto,
+                &self.codegen_units,
+                &self.panic,
+                &self.strip,
+                &self.debug,
+            ] {
+                matrix = matrix
+                    .into_iter()
+                    .flat_map(|settings: Vec<String>| {
+                        values.iter().map(move |v| {
+                            let mut settings = settings.clone();
+                            settings.push(v.clone());
+                            settings
+                        })
+                    })
+                    .collect();
+            }
+
+            println!(
+                "Building {} configurations from scratch. Pass fewer values to `--lto`, \
+                 `--codegen-units`, `--panic`, `--strip` or `--debug` to build less.",
+                matrix.len()
+            );
+            if matrix.len() > self.max_builds {
+                let interactive = io::stdin().is_terminal() && io::stderr().is_terminal();
+                if !interactive {
+                    bail!(
+                        "{} configurations exceed `--max-builds {}`",
+                        matrix.len(),
+                        self.max_builds
+                    )
+                }
+
+                let confirmed = dialoguer::Confirm::new()
+                    .with_prompt(format!(
+                        "{} configurations exceed `--max-builds {}`. Build them all?",
+                        matrix.len(),
+                        self.max_builds
+                    ))
+                    .default(false)
+                    .interact()
+                    .context("failed to confirm the builds")?;
+                if !confirmed {
+                    return Ok(());
+                }
+            }
+
+            let mut measurements = vec![];
+            for (i, settings) in matrix.into_iter().enumerate() {
+                let description = describe(&settings);
+                let target_dir = self
+                    .build_target
+                    .target_dir()?
+                    .join("ddt-profile-tune")
+                    .join(i.to_string());
+                if target_dir.exists() {
+                    std::fs::remove_dir_all(&target_dir)
+                        .with_context(|| format!("failed to clean {}", target_dir.display()))?;
+                }
+
+                let mut envs = vec![(
+                    "CARGO_TARGET_DIR".to_string(),
+                    target_dir.to_string_lossy().to_string(),
+                )];
+                for (key, v) in KEYS.iter().zip(&settings) {
+                    envs.push((
+                        format!(
+                            "{}{}",
+                            env_prefix,
+                            key.to_ascii_uppercase().replace('-', "_")
+                        ),
+                        v.clone(),
+                    ));
+                }
+
+                let start = Instant::now();
+                let build_target = self.build_target.with_target_dir(target_dir.clone());
+                let analysis =
+                    analyze_build_in(&build_target, None, envs.clone(), json_output.as_ref())
+                        .await
+                        .with_context(|| format!("failed to build with {}", description))?;
+                let compile_time = start.elapsed();
+
+                let runtime = self.bench.measure_with(&description, &envs).await?;
+
+                // The artifacts are not reused.
+                let _ = std::fs::remove_dir_all(&target_dir);
+
+                measurements.push(Measurement {
+                    settings,
+                    size: analysis.file_size,
+                    compile_time,
+                    runtime,
+                    pareto: false,
+                });
+            }
+
+            for i in 0..measurements.len() {
+                measurements[i].pareto = !measurements
+                    .iter()
+                    .any(|other| other.dominates(&measurements[i]));
+            }
+
+            print_table(&measurements);
+
+            let frontier = measurements
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| m.pareto)
+                .collect::<Vec<_>>();
+
+            let chosen = match self.choose {
+                Some(index) => {
+                    if index >= measurements.len() {
+                        bail!("there are only {} configurations", measurements.len())
+                    }
+                    Some(&measurements[index])
+                }
+                None => dialoguer::Select::new()
+                    .with_prompt(format!(
+                        "Select the configuration to write to `[profile.{}]` (Esc to skip)",
+                        profile_name
+                    ))
+                    .items(
+                        &frontier
+                            .iter()
+                            .map(|(i, m)| format!("[{}] {}", i, describe(&m.settings)))
+                            .collect::<Vec<_>>(),
+                    )
+                    .interact_opt()
+                    .context("failed to select the configuration")?
+                    .map(|selected| frontier[selected].1),
+            };
+
+            if let Some(chosen) = chosen {
+                write_profile(&profile_name, &chosen.settings)?;
+                println!(
+                    "Wrote {} to `[profile.{}]`",
+                    describe(&chosen.settings),
+                    profile_name
+                );
+            }
+
+            Ok(())
+        })
+        .await
+        .context("failed to tune the profile")
+    }
+}
+
+fn describe(settings: &[String]) -> String {
+    KEYS.iter()
+        .zip(settings)
+        .map(|(k, v)| format!("{} = {}", k, v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn print_table(measurements: &[Measurement]) {
+    let mut order = (0..measurements.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| measurements[i].size);
+
+    println!(
+        "  {:>3}  {:>6} {:>13} {:>6} {:>6} {:>5}  {:>12} {:>10} {:>10}",
+        "#", "lto", "codegen-units", "panic", "strip", "debug", "size", "compile", "runtime"
+    );
+
+    for i in order {
+        let m = &measurements[i];
+        println!(
+            "{} {:>3}  {:>6} {:>13} {:>6} {:>6} {:>5}  {:>12} {:>9.1}s {:>10}",
+            if m.pareto { "*" } else { " " },
+            i,
+            m.settings[0],
+            m.settings[1],
+            m.settings[2],
+            m.settings[3],
+            m.settings[4],
+            format_size(m.size, DECIMAL),
+            m.compile_time.as_secs_f64(),
+            m.runtime
+                .map(|r| format!("{:.3}s", r.as_secs_f64()))
+                .unwrap_or_else(|| "-".into())
+        );
+    }
+
+    println!("* is on the Pareto frontier of size, compile time and runtime");
+}
+
+/// Converts a value from the command line to the TOML type cargo expects.
+fn to_toml(v: &str) -> Item {
+    if let Ok(v) = v.parse::<i64>() {
+        return value(v);
+    }
+
+    match v {
+        "true" => value(true),
+        "false" => value(false),
+        _ => value(v),
+    }
+}
+
+fn write_profile(profile_name: &str, settings: &[String]) -> Result<()> {
+    let root_cargo_toml_path =
+        cargo_root_manifest().context("failed to get the root cargo.toml")?;
+    let root_content = std::fs::read_to_string(&root_cargo_toml_path)
+        .context("failed to read the root cargo.toml")?;
+
+    let mut toml = root_content
+        .parse::<DocumentMut>()
+        .context("failed to parse the root cargo.toml")?;
+
+    if !toml.get("profile").is_some_and(|p| p.is_table()) {
+        toml["profile"] = table();
+    }
+
+    if !toml["profile"]
+        .get(profile_name)
+        .is_some_and(|p| p.is_table())
+    {
+        toml["profile"][profile_name] = table();
+    }
+
+    for (key, v) in KEYS.iter().zip(settings) {
+        toml["profile"][profile_name][*key] = to_toml(v);
+    }
+
+    std::fs::write(root_cargo_toml_path, toml.to_string())
+        .context("failed to write the root cargo.toml")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn measurement(size: u64, compile_secs: u64, runtime_ms: Option<u64>) -> Measurement {
+        Measurement {
+            settings: vec![],
+            size,
+            compile_time: Duration::from_secs(compile_secs),
+            runtime: runtime_ms.map(Duration::from_millis),
+            pareto: false,
+        }
+    }
+
+    #[test]
+    fn pareto_dominance() {
+        let base = measurement(1000, 10, Some(100));
+
+        assert!(measurement(900, 10, Some(100)).dominates(&base));
+        assert!(measurement(1000, 9, Some(90)).dominates(&base));
+        // Equal is not better.
+        assert!(!measurement(1000, 10, Some(100)).dominates(&base));
+        // A trade-off.
+        assert!(!measurement(900, 20, Some(100)).dominates(&base));
+        assert!(!base.dominates(&measurement(900, 20, Some(100))));
+
+        // Without a benchmark, only the size and the compile time count.
+        assert!(measurement(900, 10, None).dominates(&measurement(1000, 10, None)));
+    }
+
+    #[test]
+    fn profile_values() {
+        assert_eq!(to_toml("16").to_string(), "16");
+        assert_eq!(to_toml("true").to_string(), "true");
+        assert_eq!(to_toml("thin").to_string(), "\"thin\"");
+        assert_eq!(to_toml("off").to_string(), "\"off\"");
+
+        assert_eq!(
+            describe(&["thin".into(), "1".into(), "abort".into()]),
+            "lto = thin, codegen-units = 1, panic = abort"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Context, Result};
+use cargo_metadata::DependencyKind;
+use clap::{Args, ValueEnum};
+use semver::VersionReq;
+
+use crate::util::{
+    cargo_build::{run_cargo_metadata_with_deps_for, FeatureArgs},
+    dep_graph::{DepGraph, Edge},
+    wrap,
+};
+
+/// Explain why a crate is in the dependency graph, by printing the paths from
+/// the workspace members to it.
+#[derive(Debug, Args)]
+pub(super) struct WhyCommand {
+    /// The crate to explain. Use `name@version` to select versions.
+    #[clap(name = "CRATE")]
+    spec: String,
+
+    /// Maximum number of paths to print for each version.
+    #[clap(long, default_value_t = 3)]
+    max_paths: usize,
+
+    /// Only follow the dependencies of these kinds.
+    #[clap(long, value_enum)]
+    kind: Vec<EdgeKind>,
+
+    #[clap(flatten)]
+    features: FeatureArgs,
+
+    /// Only consider the dependencies for the target triple.
+    #[clap(long)]
+    target: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EdgeKind {
+    Normal,
+    Build,
+    Dev,
+}
+
+impl EdgeKind {
+    fn matches(self, kind: DependencyKind) -> bool {
+        matches!(
+            (self, kind),
+            (EdgeKind::Normal, DependencyKind::Normal)
+                | (EdgeKind::Build, DependencyKind::Build)
+                | (EdgeKind::Dev, DependencyKind::Development)
+        )
+    }
+}
+
+impl WhyCommand {
+    pub async fn run(self) -> Result<()> {
+        let spec = self.spec.clone();
+
+        wrap(async move {
+            let (name, req) = match self.spec.split_once('@') {
+                Some((name, req)) => (
+                    name,
+                    req.parse::<VersionReq>()
+                        .with_context(|| format!("invalid version requirement `{}`", req))?,
+                ),
+                None => (&*self.spec, VersionReq::STAR),
+            };
+
+            let md = run_cargo_metadata_with_deps_for(self.features.clone(), self.target.clone())?;
+            let graph = DepGraph::new(&md)?;
+
+            let mut targets = graph
+                .packages()
+                .filter(|p| p.name == name && req.matches(&p.version))
+                .collect::<Vec<_>>();
+            targets.sort_by(|a, b| a.version.cmp(&b.version));
+
+            if targets.is_empty() {
+                bail!("`{}` is not in the dependency graph", self.spec)
+            }
+
+            let filter = edge_filter(&self.kind);
+
+            for pkg in targets {
+                let features = graph.enabled_features(&pkg.id);
+                if features.is_empty() {
+                    println!("{} v{}", pkg.name, pkg.version);
+                } else {
+                    println!(
+                        "{} v{} (features: {})",
+                        pkg.name,
+                        pkg.version,
+                        features.join(", ")
+                    );
+                }
+
+                // One more, to know if there are more paths.
+                let paths = graph.paths_to_with(&pkg.id, self.max_paths + 1, &filter);
+
+                if paths.is_empty() {
+                    println!("  (no path from the workspace members)");
+                }
+
+                for path in paths.iter().take(self.max_paths) {
+                    let Some(first) = path.first() else {
+                        println!("  (workspace member)");
+                        continue;
+                    };
+
+                    let root = graph.package(first.from);
+                    println!();
+                    println!("  {} v{}", root.name, root.version);
+
+                    for (depth, edge) in path.iter().enumerate() {
+                        let child = graph.package(edge.to);
+
+                        println!(
+                            "  {:indent$}└─ {} v{} [{}]",
+                            "",
+                            child.name,
+                            child.version,
+                            describe_edge(&graph, edge),
+                            indent = depth * 3
+                        );
+                    }
+                }
+
+                if paths.len() > self.max_paths {
+                    println!();
+                    println!("  ... (use --max-paths to print more paths)");
+                }
+
+                println!();
+            }
+
+            Ok(())
+        })
+        .await
+        .with_context(|| format!("failed to explain `{}`", spec))
+    }
+}
+
+/// Accepts the edges with one of `kinds`, or all edges if `kinds` is empty.
+fn edge_filter(kinds: &[EdgeKind]) -> impl Fn(&Edge) -> bool + '_ {
+    move |edge| {
+        if kinds.is_empty() {
+            return true;
+        }
+
+        if edge.dep.dep_kinds.is_empty() {
+            return kinds.contains(&EdgeKind::Normal);
+        }
+
+        edge.dep
+            .dep_kinds
+            .iter()
+            .any(|k| kinds.iter().any(|kind| kind.matches(k.kind)))
+    }
+}
+
+/// Describes the dependency kinds and the features of an edge.
+fn describe_edge(graph: &DepGraph, edge: &Edge) -> String {
+    let parent = graph.package(edge.from);
+    let child = graph.package(edge.to);
+
+    let mut parts = vec![];
+
+    let kinds = edge
+        .dep
+        .dep_kinds
+        .iter()
+        .map(|k| match &k.target {
+            Some(target) => format!("{} ({})", kind_name(k.kind), target),
+            None => kind_name(k.kind).to_string(),
+        })
+        .collect::<Vec<_>>();
+    if kinds.is_empty() {
+        parts.push(kind_name(DependencyKind::Normal).to_string());
+    } else {
+        parts.push(kinds.join(", "));
+    }
+
+    let mut enabled_by = BTreeSet::new();
+    let mut features = BTreeSet::new();
+
+    let parent_features = graph.enabled_features(edge.from);
+
+    for decl in parent.dependencies.iter().filter(|d| {
+        d.name == child.name
+            && d.req.matches(&child.version)
+            && (edge.dep.dep_kinds.is_empty()
+                || edge.dep.dep_kinds.iter().any(|k| k.kind == d.kind))
+    }) {
+        if decl.uses_default_features {
+            features.insert("default");
+        }
+        features.extend(decl.features.iter().map(|s| &**s));
+
+        if decl.optional {
+            let local_name = decl.rename.as_deref().unwrap_or(&decl.name);
+
+            for feature in parent_features {
+                let Some(values) = parent.features.get(feature) else {
+                    continue;
+                };
+
+                if values.iter().any(|v| enables_dependency(v, local_name)) {
+                    enabled_by.insert(&**feature);
+                }
+            }
+        }
+    }
+
+    if !enabled_by.is_empty() {
+        parts.push(format!(
+            "optional, enabled by {}",
+            enabled_by
+                .iter()
+                .map(|f| format!("`{}`", f))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    if !features.is_empty() {
+        parts.push(format!(
+            "features: {}",
+            features.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    parts.join("; ")
+}
+
+/// Returns true if the feature value enables the optional dependency.
+///
+/// Weak dependency features (`dep?/feature`) do not enable the dependency.
+fn enables_dependency(value: &str, local_name: &str) -> bool {
+    if let Some(dep) = value.strip_prefix("dep:") {
+        return dep == local_name;
+    }
+
+    match value.split_once('/') {
+        Some((dep, _)) => dep == local_name,
+        None => value == local_name,
+    }
+}
+
+fn kind_name(kind: DependencyKind) -> &'static str {
+    match kind {
+        DependencyKind::Normal => "normal",
+        DependencyKind::Development => "dev",
+        DependencyKind::Build => "build",
+        DependencyKind::Unknown => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::dep_graph::fixture::{id, workspace};
+
+    #[test]
+    fn filter_by_kind() {
+        let md = workspace();
+        let graph = DepGraph::new(&md).unwrap();
+        let c1 = id(&md, "c@1.0.0");
+
+        let count = |kinds: &[EdgeKind]| {
+            graph
+                .paths_to_with(c1, usize::MAX, edge_filter(kinds))
+                .len()
+        };
+
+        assert_eq!(count(&[]), 3);
+        assert_eq!(count(&[EdgeKind::Normal]), 2);
+        assert_eq!(count(&[EdgeKind::Build]), 0);
+        assert_eq!(count(&[EdgeKind::Normal, EdgeKind::Build]), 3);
+    }
+
+    #[test]
+    fn optional_dependency_features() {
+        assert!(enables_dependency("dep:serde", "serde"));
+        assert!(enables_dependency("serde", "serde"));
+        assert!(enables_dependency("serde/derive", "serde"));
+        assert!(!enables_dependency("dep:serde_json", "serde"));
+        assert!(!enables_dependency("serde?/derive", "serde"));
+        assert!(!enables_dependency("std", "serde"));
+    }
+}
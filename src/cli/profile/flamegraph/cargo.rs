@@ -5,9 +5,15 @@ use clap::Args;
 
 use super::run::RunCommand;
 use crate::{
-    cli::util::cargo::get_one_binary_using_cargo,
+    cli::util::{
+        cargo::{
+            command_with_runner, get_binaries_using_cargo, BinarySelection, ProfilingBuildArgs,
+        },
+        harness::HarnessArgs,
+        platform::PostBuildArgs,
+    },
     util::{
-        cargo_build::{cargo_target_dir, CargoBuildTarget},
+        cargo_build::{CargoBuildTarget, DiagnosticsArgs},
         wrap,
     },
 };
@@ -19,7 +25,7 @@ pub(super) struct CargoCommand {
     time_limit: Option<usize>,
 
     /// The path to the output flamegraph file
-    #[clap(long, short = 'o')]
+    #[clap(long, short = 'o', conflicts_with = "all_binaries")]
     output_path: Option<PathBuf>,
 
     #[clap(long)]
@@ -31,6 +37,21 @@ pub(super) struct CargoCommand {
     #[clap(flatten)]
     build_target: CargoBuildTarget,
 
+    #[clap(flatten)]
+    profiling_build: ProfilingBuildArgs,
+
+    #[clap(flatten)]
+    selection: BinarySelection,
+
+    #[clap(flatten)]
+    post_build: PostBuildArgs,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+
+    #[clap(flatten)]
+    harness: HarnessArgs,
+
     /// Arguments passed to the target binary.
     ///
     /// To pass flags, precede child args with `--`,
@@ -40,39 +61,64 @@ pub(super) struct CargoCommand {
 
 impl CargoCommand {
     pub async fn run(self) -> Result<()> {
-        let (cmd, envs) = wrap(async move {
-            let (bin, envs) = get_one_binary_using_cargo(&self.build_target).await?;
-
-            let target_shortname = bin
-                .path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .ok_or_else(|| anyhow!("invalid target path {:?}", bin.path))?;
-            let now = chrono::Local::now();
-
-            let output_path = cargo_target_dir()?.join("flamegraph").join(format!(
-                "{}_{}.svg",
-                target_shortname,
-                now.format("%F_%H%M%S-%3f")
-            ));
-
-            Ok((
-                RunCommand {
-                    bin: bin.path,
-                    time_limit: self.time_limit,
-                    output_path: Some(output_path),
-                    no_open: self.no_open,
-                    root: self.root,
-                    args: self.args,
-                },
-                envs,
-            ))
+        let cmds = wrap(async move {
+            let bins = get_binaries_using_cargo(
+                &self.build_target,
+                &self.profiling_build,
+                &self.selection,
+                &self.post_build,
+                &self.diagnostics,
+            )
+            .await?;
+
+            let mut cmds = vec![];
+            for (bin, envs) in bins {
+                let target_shortname = bin
+                    .path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .ok_or_else(|| anyhow!("invalid target path {:?}", bin.path))?;
+                let now = chrono::Local::now();
+
+                let output_path = match &self.output_path {
+                    Some(path) => path.clone(),
+                    None => self.build_target.output_dir("flamegraph")?.join(format!(
+                        "{}_{}.svg",
+                        target_shortname,
+                        now.format("%F_%H%M%S-%3f")
+                    )),
+                };
+
+                let (bin, args) = command_with_runner(
+                    &self.build_target,
+                    bin.path.clone(),
+                    self.harness.args_for(&bin, &self.args)?,
+                )?;
+
+                cmds.push((
+                    RunCommand {
+                        bin,
+                        time_limit: self.time_limit,
+                        output_path: Some(output_path),
+                        no_open: self.no_open,
+                        root: self.root,
+                        args,
+                    },
+                    envs,
+                ));
+            }
+
+            Ok(cmds)
         })
         .await
         .context("failed to build the target binary using cargo")?;
 
-        cmd.run(envs)
-            .await
-            .context("failed to run `ddt profile flamegraph run` with the built binary")
+        for (cmd, envs) in cmds {
+            cmd.run(envs)
+                .await
+                .context("failed to run `ddt profile flamegraph run` with the built binary")?;
+        }
+
+        Ok(())
     }
 }
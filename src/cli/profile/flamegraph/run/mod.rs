@@ -1,7 +1,7 @@
 use std::{
     fs::OpenOptions,
     io::{BufWriter, Cursor},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
 
 use anyhow::{bail, Context, Result};
@@ -87,13 +87,22 @@ impl RunCommand {
             };
             let mut collapsed = Cursor::new(collapsed);
 
-            // TODO
-            let flamegraph_file_path = Path::new("flamegraph.svg");
+            let flamegraph_file_path = self
+                .output_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("flamegraph.svg"));
+            if let Some(dir) = flamegraph_file_path.parent() {
+                if !dir.as_os_str().is_empty() {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("failed to create {}", dir.display()))?;
+                }
+            }
+
             let flamegraph_file = OpenOptions::new()
                 .write(true)
                 .truncate(true)
                 .create(true)
-                .open(flamegraph_file_path)
+                .open(&flamegraph_file_path)
                 .context("unable to create flamegraph.svg output file")?;
 
             let flamegraph_writer = BufWriter::new(flamegraph_file);
@@ -115,7 +124,7 @@ impl RunCommand {
             info!("Flamegraph printed to {}", flamegraph_file_path.display());
 
             if !self.no_open {
-                let _ = open_file(flamegraph_file_path);
+                let _ = open_file(&flamegraph_file_path);
             }
 
             Ok(())
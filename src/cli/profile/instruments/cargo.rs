@@ -3,9 +3,18 @@ use clap::Args;
 
 use super::{run::RunCommand, util::file_name_for_trace_file};
 use crate::{
-    cli::{profile::instruments::util::XcodeInstruments, util::cargo::get_one_binary_using_cargo},
+    cli::{
+        profile::instruments::util::XcodeInstruments,
+        util::{
+            cargo::{
+                command_with_runner, get_binaries_using_cargo, BinarySelection, ProfilingBuildArgs,
+            },
+            harness::HarnessArgs,
+            platform::PostBuildArgs,
+        },
+    },
     util::{
-        cargo_build::{cargo_target_dir, CargoBuildTarget},
+        cargo_build::{CargoBuildTarget, DiagnosticsArgs},
         wrap,
     },
 };
@@ -25,6 +34,21 @@ pub(super) struct CargoCommand {
     #[clap(flatten)]
     build_target: CargoBuildTarget,
 
+    #[clap(flatten)]
+    profiling_build: ProfilingBuildArgs,
+
+    #[clap(flatten)]
+    selection: BinarySelection,
+
+    #[clap(flatten)]
+    post_build: PostBuildArgs,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+
+    #[clap(flatten)]
+    harness: HarnessArgs,
+
     /// Arguments passed to the target binary.
     ///
     /// To pass flags, precede child args with `--`,
@@ -34,30 +58,51 @@ pub(super) struct CargoCommand {
 
 impl CargoCommand {
     pub async fn run(self, xctrace_tool: XcodeInstruments) -> Result<()> {
-        let (cmd, envs) = wrap(async move {
-            let (bin, envs) = get_one_binary_using_cargo(&self.build_target).await?;
-
-            let output_path = cargo_target_dir()?
-                .join("instruments")
-                .join(file_name_for_trace_file(&bin.path, &self.template)?);
-
-            Ok((
-                RunCommand {
-                    template: self.template,
-                    time_limit: self.time_limit,
-                    no_open: self.no_open,
-                    args: self.args,
-                    bin: bin.path,
-                    output_path: Some(output_path),
-                },
-                envs,
-            ))
+        let cmds = wrap(async move {
+            let bins = get_binaries_using_cargo(
+                &self.build_target,
+                &self.profiling_build,
+                &self.selection,
+                &self.post_build,
+                &self.diagnostics,
+            )
+            .await?;
+
+            bins.into_iter()
+                .map(|(bin, envs)| {
+                    let output_path = self
+                        .build_target
+                        .output_dir("instruments")?
+                        .join(file_name_for_trace_file(&bin.path, &self.template)?);
+                    let (bin, args) = command_with_runner(
+                        &self.build_target,
+                        bin.path.clone(),
+                        self.harness.args_for(&bin, &self.args)?,
+                    )?;
+
+                    Ok((
+                        RunCommand {
+                            template: self.template.clone(),
+                            time_limit: self.time_limit,
+                            no_open: self.no_open,
+                            args,
+                            bin,
+                            output_path: Some(output_path),
+                        },
+                        envs,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()
         })
         .await
         .context("failed to build the target binary using cargo")?;
 
-        cmd.run(xctrace_tool, envs)
-            .await
-            .context("failed to run `ddt profile instruments run` with the built binary")
+        for (cmd, envs) in cmds {
+            cmd.run(xctrace_tool, envs)
+                .await
+                .context("failed to run `ddt profile instruments run` with the built binary")?;
+        }
+
+        Ok(())
     }
 }
@@ -3,8 +3,17 @@ use clap::Args;
 
 use super::run::RunCommand;
 use crate::{
-    cli::util::cargo::get_one_binary_using_cargo,
-    util::{cargo_build::CargoBuildTarget, wrap},
+    cli::util::{
+        cargo::{
+            command_with_runner, get_binaries_using_cargo, BinarySelection, ProfilingBuildArgs,
+        },
+        harness::HarnessArgs,
+        platform::PostBuildArgs,
+    },
+    util::{
+        cargo_build::{CargoBuildTarget, DiagnosticsArgs},
+        wrap,
+    },
 };
 
 /// Invoke a binary file built using `cargo` under the `instruments` tool.
@@ -19,6 +28,21 @@ pub(super) struct CargoCommand {
     #[clap(flatten)]
     build_target: CargoBuildTarget,
 
+    #[clap(flatten)]
+    profiling_build: ProfilingBuildArgs,
+
+    #[clap(flatten)]
+    selection: BinarySelection,
+
+    #[clap(flatten)]
+    post_build: PostBuildArgs,
+
+    #[clap(flatten)]
+    diagnostics: DiagnosticsArgs,
+
+    #[clap(flatten)]
+    harness: HarnessArgs,
+
     /// Arguments passed to the target binary.
     ///
     /// To pass flags, precede child args with `--`,
@@ -28,24 +52,45 @@ pub(super) struct CargoCommand {
 
 impl CargoCommand {
     pub async fn run(self) -> Result<()> {
-        let (cmd, envs) = wrap(async move {
-            let (bin, envs) = get_one_binary_using_cargo(&self.build_target).await?;
-
-            Ok((
-                RunCommand {
-                    time_limit: self.time_limit,
-                    no_open: self.no_open,
-                    args: self.args,
-                    bin: bin.path,
-                },
-                envs,
-            ))
+        let cmds = wrap(async move {
+            let bins = get_binaries_using_cargo(
+                &self.build_target,
+                &self.profiling_build,
+                &self.selection,
+                &self.post_build,
+                &self.diagnostics,
+            )
+            .await?;
+
+            bins.into_iter()
+                .map(|(bin, envs)| {
+                    let (bin, args) = command_with_runner(
+                        &self.build_target,
+                        bin.path.clone(),
+                        self.harness.args_for(&bin, &self.args)?,
+                    )?;
+
+                    Ok((
+                        RunCommand {
+                            time_limit: self.time_limit,
+                            no_open: self.no_open,
+                            args,
+                            bin,
+                        },
+                        envs,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()
         })
         .await
         .context("failed to build the target binary using cargo")?;
 
-        cmd.run(envs)
-            .await
-            .context("failed to run `ddt profile samply run` with the built binary")
+        for (cmd, envs) in cmds {
+            cmd.run(envs)
+                .await
+                .context("failed to run `ddt profile samply run` with the built binary")?;
+        }
+
+        Ok(())
     }
 }
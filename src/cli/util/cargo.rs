@@ -1,107 +1,411 @@
-use std::process::Command;
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
 
 use anyhow::{bail, Context, Result};
 use cached::proc_macro::cached;
+use clap::Args;
 use dialoguer::Select;
 use hstr::Atom;
+use regex::Regex;
 use tempfile::tempdir;
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::util::cargo_build::{
-    cargo_workspace_dir, compile, run_cargo_metadata_with_deps, BinFile, CargoBuildTarget,
+use super::platform::{platform_for, PostBuildArgs};
+use crate::util::{
+    cancellable,
+    cargo_build::{
+        compile_with, host_triple, run_cargo_metadata_no_deps_for, run_cargo_metadata_with_deps,
+        target_cfgs, BinFile, CargoBuildTarget, DiagnosticsArgs,
+    },
+    cargo_config::{config_env, rustflags},
 };
 
-pub async fn get_one_binary_using_cargo(
+/// Options to select the binaries to run, if the build produces multiple
+/// binaries.
+#[derive(Debug, Clone, Args)]
+pub struct BinarySelection {
+    /// Select the binary whose target name or path matches this regex.
+    #[clap(long)]
+    pub pick: Option<Regex>,
+
+    /// Select the binary by its index in the list of built binaries, which is
+    /// sorted by path.
+    #[clap(long, conflicts_with = "pick")]
+    pub pick_index: Option<usize>,
+
+    /// Run every built binary in sequence.
+    #[clap(long, conflicts_with_all = ["pick", "pick_index"])]
+    pub all_binaries: bool,
+}
+
+/// Options to build the binaries with the settings useful for profiling,
+/// without editing `Cargo.toml`.
+#[derive(Debug, Clone, Args)]
+pub struct ProfilingBuildArgs {
+    /// Build with debug info and frame pointers, in a separate target
+    /// directory so the normal build cache is not invalidated.
+    #[clap(long)]
+    pub profiling_build: bool,
+
+    /// Also use the v0 symbol mangling, which keeps the generic arguments in
+    /// the symbol names.
+    #[clap(long, requires = "profiling_build")]
+    pub v0_symbols: bool,
+}
+
+impl ProfilingBuildArgs {
+    /// Returns the build target and the environment variables for the
+    /// profiling build.
+    fn apply(
+        &self,
+        build_target: &CargoBuildTarget,
+    ) -> Result<(CargoBuildTarget, Vec<(String, String)>)> {
+        if !self.profiling_build {
+            return Ok((build_target.clone(), vec![]));
+        }
+
+        let profile = build_target
+            .profile_name()
+            .to_ascii_uppercase()
+            .replace('-', "_");
+        let triple = match build_target.target_triple()? {
+            Some(triple) => triple,
+            None => host_triple()?,
+        };
+
+        // The flags of the environment and `.cargo/config.toml` are kept, as
+        // `CARGO_ENCODED_RUSTFLAGS` overrides them.
+        let mut flags = rustflags(
+            &build_target.cargo_config_files()?,
+            &triple,
+            &target_cfgs(triple.clone())?,
+        );
+        flags.push("-Cforce-frame-pointers=yes".into());
+        if self.v0_symbols {
+            flags.push("-Csymbol-mangling-version=v0".into());
+        }
+
+        let envs = vec![
+            (format!("CARGO_PROFILE_{}_DEBUG", profile), "true".into()),
+            // `strip` would remove the debug info.
+            (format!("CARGO_PROFILE_{}_STRIP", profile), "none".into()),
+            ("CARGO_ENCODED_RUSTFLAGS".into(), flags.join("\x1f")),
+        ];
+
+        let build_target =
+            build_target.with_target_dir(build_target.target_dir()?.join("ddt-profiling"));
+
+        Ok((build_target, envs))
+    }
+}
+
+/// Builds the binaries using cargo and returns the selected ones, with the
+/// environment variables to run them.
+pub async fn get_binaries_using_cargo(
     build_target: &CargoBuildTarget,
-) -> Result<(BinFile, Vec<(String, String)>)> {
-    let bins = compile(build_target).context("failed to build the binary using cargo")?;
+    profiling_build: &ProfilingBuildArgs,
+    selection: &BinarySelection,
+    post_build: &PostBuildArgs,
+    diagnostics: &DiagnosticsArgs,
+) -> Result<Vec<(BinFile, Vec<(String, String)>)>> {
+    let (build_target, envs) = profiling_build.apply(build_target)?;
+    let build_target = &build_target;
+    let json_output = diagnostics.open().await?;
+
+    cancellable(async move {
+        let bins = compile_with(build_target, None, &envs, json_output.as_ref(), &[])
+            .await
+            .context("failed to build the binary using cargo")?;
+
+        let interactive = io::stdin().is_terminal() && io::stderr().is_terminal();
+        let bins = select_binaries(bins, build_target, selection, interactive)?;
 
+        let mut prepared = vec![];
+        for bin in bins {
+            prepared.push(prepare_binary(bin, build_target, post_build).await?);
+        }
+
+        Ok(prepared)
+    })
+    .await
+}
+
+/// Selects the binaries using the options, falling back to the target named
+/// by `--bin`, `--test`, `--bench` or `--example`, and then to a prompt.
+fn select_binaries(
+    mut bins: Vec<BinFile>,
+    build_target: &CargoBuildTarget,
+    selection: &BinarySelection,
+    interactive: bool,
+) -> Result<Vec<BinFile>> {
     if bins.is_empty() {
         bail!("cargo build did not produce any binaries")
     }
 
-    let bin = if bins.len() == 1 {
-        bins.into_iter().next().unwrap()
-    } else {
-        let items = bins
-            .iter()
-            .map(|bin| format!("[{}] {}", bin.crate_name, bin.path.display()))
-            .collect::<Vec<_>>();
+    if selection.all_binaries {
+        return Ok(bins);
+    }
 
-        let selection = Select::new()
-            .with_prompt("What do you choose?")
-            .items(&items)
-            .interact()
-            .unwrap();
+    if let Some(index) = selection.pick_index {
+        if index >= bins.len() {
+            bail!(
+                "`--pick-index {}` is out of range; the built binaries are:\n{}",
+                index,
+                list_binaries(&bins)
+            )
+        }
 
-        bins.into_iter().nth(selection).unwrap()
-    };
+        return Ok(vec![bins.swap_remove(index)]);
+    }
 
-    {
-        let mut cmd = Command::new("codesign");
-        cmd.arg("-s").arg("-").arg("-v").arg("-f");
+    if let Some(pick) = &selection.pick {
+        let candidates = bins.len();
+        bins.retain(|bin| {
+            pick.is_match(&bin.crate_name) || pick.is_match(&bin.path.to_string_lossy())
+        });
 
-        let tmp_dir = tempdir()?;
-        let plist = tmp_dir.path().join("entitlements.xml");
+        if bins.is_empty() {
+            bail!(
+                "`--pick {}` matched none of the {} built binaries",
+                pick,
+                candidates
+            )
+        }
+    }
 
-        let entitlements = r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-    <dict>
-        <key>com.apple.security.get-task-allow</key>
-        <true/>
-    </dict>
-</plist>
-        
-        "#;
+    if bins.len() == 1 {
+        return Ok(bins);
+    }
 
-        std::fs::write(&plist, entitlements).context("failed to write the entitlements file")?;
+    let named = [
+        &build_target.bin,
+        &build_target.test,
+        &build_target.bench,
+        &build_target.example,
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    let matching = bins
+        .iter()
+        .filter(|bin| named.contains(&&bin.crate_name))
+        .count();
+    if matching == 1 {
+        bins.retain(|bin| named.contains(&&bin.crate_name));
+        return Ok(bins);
+    }
 
-        cmd.arg("--entitlements").arg(&plist);
+    if !interactive {
+        bail!(
+            "the build produced multiple binaries; select one using `--pick <regex>` or \
+             `--pick-index <index>`, or use `--all-binaries`:\n{}",
+            list_binaries(&bins)
+        )
+    }
 
-        cmd.arg(&bin.path);
+    let items = bins
+        .iter()
+        .map(|bin| format!("[{}] {}", bin.crate_name, bin.path.display()))
+        .collect::<Vec<_>>();
 
-        info!("Running codesign on the built binary...");
-        let status = cmd.status().context("failed to codesign the binary")?;
+    let selected = Select::new()
+        .with_prompt("What do you choose?")
+        .items(&items)
+        .interact()
+        .context("failed to select the binary")?;
 
-        if !status.success() {
-            bail!("failed to codesign the binary")
-        }
+    Ok(vec![bins.swap_remove(selected)])
+}
+
+fn list_binaries(bins: &[BinFile]) -> String {
+    bins.iter()
+        .enumerate()
+        .map(|(i, bin)| format!("  {}: [{}] {}", i, bin.crate_name, bin.path.display()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs the platform-specific steps on the binary, and returns the
+/// environment variables to run it.
+async fn prepare_binary(
+    bin: BinFile,
+    build_target: &CargoBuildTarget,
+    post_build: &PostBuildArgs,
+) -> Result<(BinFile, Vec<(String, String)>)> {
+    let triple = match build_target.target_triple()? {
+        Some(triple) => triple,
+        None => host_triple()?,
+    };
+    let platform = platform_for(&triple);
+
+    let data = tokio::fs::read(&bin.path)
+        .await
+        .with_context(|| format!("failed to read {}", bin.path.display()))?;
+    for warning in platform.check(&data) {
+        warn!("{}: {}", bin.path.display(), warning);
     }
 
-    if cfg!(target_os = "macos") {
-        info!("Running dsymutil on the built binary...");
+    let work_dir = tempdir()?;
+    for step in platform.steps(&bin.path, &data, post_build, work_dir.path())? {
+        info!("{}...", step.description);
 
-        let status = Command::new("dsymutil")
-            .arg(&bin.path)
+        let cmd_str = format!("{:?}", step.cmd);
+        let status = tokio::process::Command::from(step.cmd)
+            .kill_on_drop(true)
             .status()
-            .context("failed to run dsymutil on the binary")?;
-
+            .await
+            .with_context(|| format!("failed to run {}", cmd_str))?;
         if !status.success() {
-            bail!("failed to run dsymutil on the binary")
+            bail!("{} failed with {}", cmd_str, status)
         }
     }
 
+    let envs = launch_envs(&bin, build_target)?;
+
+    Ok((bin, envs))
+}
+
+/// Returns the environment variables cargo sets for `cargo run` and
+/// `cargo test`, followed by the `[env]` entries of `.cargo/config.toml`.
+fn launch_envs(bin: &BinFile, build_target: &CargoBuildTarget) -> Result<Vec<(String, String)>> {
+    let md = run_cargo_metadata_no_deps_for(bin.manifest_path.clone())?;
+    let pkg = md
+        .packages
+        .iter()
+        .find(|pkg| pkg.id == bin.package_id)
+        .with_context(|| format!("failed to find the package `{}`", bin.package_id))?;
+
     let mut envs = vec![];
 
     let mut add = |key: &str, value: String| {
         envs.push((key.to_string(), value));
     };
 
+    add(
+        "CARGO",
+        env::var("CARGO").unwrap_or_else(|_| "cargo".into()),
+    );
     add(
         "CARGO_MANIFEST_DIR",
-        bin.manifest_path
-            .parent()
-            .unwrap()
-            .to_string_lossy()
-            .to_string(),
+        pkg.manifest_path.parent().unwrap().to_string(),
+    );
+    add("CARGO_MANIFEST_PATH", pkg.manifest_path.to_string());
+    add("CARGO_WORKSPACE_DIR", md.workspace_root.to_string());
+
+    add("CARGO_PKG_NAME", pkg.name.clone());
+    add("CARGO_PKG_VERSION", pkg.version.to_string());
+    add("CARGO_PKG_VERSION_MAJOR", pkg.version.major.to_string());
+    add("CARGO_PKG_VERSION_MINOR", pkg.version.minor.to_string());
+    add("CARGO_PKG_VERSION_PATCH", pkg.version.patch.to_string());
+    add("CARGO_PKG_VERSION_PRE", pkg.version.pre.to_string());
+    add("CARGO_PKG_AUTHORS", pkg.authors.join(":"));
+    add(
+        "CARGO_PKG_DESCRIPTION",
+        pkg.description.clone().unwrap_or_default(),
+    );
+    add(
+        "CARGO_PKG_HOMEPAGE",
+        pkg.homepage.clone().unwrap_or_default(),
+    );
+    add(
+        "CARGO_PKG_REPOSITORY",
+        pkg.repository.clone().unwrap_or_default(),
+    );
+    add("CARGO_PKG_LICENSE", pkg.license.clone().unwrap_or_default());
+    add(
+        "CARGO_PKG_LICENSE_FILE",
+        pkg.license_file
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+    );
+    add(
+        "CARGO_PKG_README",
+        pkg.readme
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
     );
     add(
-        "CARGO_WORKSPACE_DIR",
-        cargo_workspace_dir()?.to_string_lossy().to_string(),
+        "CARGO_PKG_RUST_VERSION",
+        pkg.rust_version
+            .as_ref()
+            .map(|v| v.to_string().trim_start_matches('^').to_string())
+            .unwrap_or_default(),
     );
 
-    Ok((bin, envs))
+    let mut lib_paths = vec![];
+
+    if let Some(script) = &bin.build_script {
+        add("OUT_DIR", script.out_dir.to_string());
+        for (key, value) in &script.env {
+            add(key, value.clone());
+        }
+
+        // Like `native=/path`.
+        lib_paths.extend(script.linked_paths.iter().map(|path| {
+            let path = path.as_str();
+            PathBuf::from(path.split_once('=').map_or(path, |(_, path)| path))
+        }));
+    }
+
+    // For dynamic libraries, like `cargo run` does.
+    lib_paths.push(build_target.artifact_dir()?.join("deps"));
+    let lib_path_var = if cfg!(windows) {
+        "PATH"
+    } else if cfg!(target_os = "macos") {
+        "DYLD_FALLBACK_LIBRARY_PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    if let Some(existing) = env::var_os(lib_path_var) {
+        lib_paths.extend(env::split_paths(&existing));
+    }
+    add(
+        lib_path_var,
+        env::join_paths(lib_paths)
+            .context("failed to join the library paths")?
+            .to_string_lossy()
+            .to_string(),
+    );
+
+    // Like cargo, the `[env]` entries do not override the variables set by
+    // cargo.
+    for (key, value) in config_env(&build_target.cargo_config_files()?, |key| {
+        env::var(key).ok()
+    }) {
+        if !envs.iter().any(|(k, _)| *k == key) {
+            envs.push((key, value));
+        }
+    }
+
+    Ok(envs)
+}
+
+/// Returns the program and the arguments to run `bin`, which is prefixed with
+/// the runner of the target (like `qemu-aarch64`) if one is configured.
+pub fn command_with_runner(
+    build_target: &CargoBuildTarget,
+    bin: PathBuf,
+    args: Vec<String>,
+) -> Result<(PathBuf, Vec<String>)> {
+    let Some(runner) = build_target.runner()? else {
+        return Ok((bin, args));
+    };
+
+    info!("Running the binary with `{}`", runner.join(" "));
+
+    let mut runner = runner.into_iter();
+    let program = runner.next().unwrap();
+
+    let mut runner_args = runner.collect::<Vec<_>>();
+    runner_args.push(bin.to_string_lossy().to_string());
+    runner_args.extend(args);
+
+    Ok((program.into(), runner_args))
 }
 
 #[cached(result = true)]
@@ -130,3 +434,66 @@ pub fn to_original_crate_name(lib_name: Atom) -> Result<Atom> {
         .map(|pkg| Atom::from(&*pkg.name))
         .ok_or_else(|| anyhow::anyhow!("failed to find the crate for library: {}", lib_name))
 }
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+
+    fn bins() -> Vec<BinFile> {
+        ["app", "bench_parse", "integration"]
+            .iter()
+            .map(|name| BinFile {
+                path: PathBuf::from(format!("target/release/deps/{}-0123", name)),
+                extra_files: vec![],
+                profile: serde_json::from_str(
+                    r#"{"opt_level":"3","debuginfo":0,"debug_assertions":false,"overflow_checks":false,"test":false}"#,
+                )
+                .unwrap(),
+                crate_name: name.to_string(),
+                manifest_path: PathBuf::from("Cargo.toml"),
+                package_id: cargo_metadata::PackageId {
+                    repr: "app 0.1.0 (path+file:///work/app)".into(),
+                },
+                build_script: None,
+            })
+            .collect()
+    }
+
+    fn select(args: &[&str]) -> Result<Vec<String>> {
+        #[derive(Parser)]
+        struct Cli {
+            #[clap(flatten)]
+            build_target: CargoBuildTarget,
+
+            #[clap(flatten)]
+            selection: BinarySelection,
+        }
+
+        let cli = Cli::parse_from(std::iter::once("ddt").chain(args.iter().copied()));
+
+        Ok(
+            select_binaries(bins(), &cli.build_target, &cli.selection, false)?
+                .into_iter()
+                .map(|bin| bin.crate_name)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn select_binaries_non_interactively() {
+        assert_eq!(select(&["--pick", "^bench"]).unwrap(), ["bench_parse"]);
+        assert_eq!(select(&["--pick-index", "2"]).unwrap(), ["integration"]);
+        assert_eq!(select(&["--test", "integration"]).unwrap(), ["integration"]);
+        assert_eq!(
+            select(&["--all-binaries"]).unwrap(),
+            ["app", "bench_parse", "integration"]
+        );
+
+        let err = select(&[]).unwrap_err().to_string();
+        assert!(err.contains("1: [bench_parse]"), "{}", err);
+        assert!(select(&["--pick", "nothing"]).is_err());
+        assert!(select(&["--pick-index", "3"]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Arguments for the harnesses of tests and benches, so the profile contains
+//! only the selected case.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use toml_edit::DocumentMut;
+use tracing::{info, warn};
+
+use crate::util::cargo_build::{run_cargo_metadata_no_deps_for, BinFile};
+
+/// Options for the binaries built from `--test`, `--tests`, `--bench` or
+/// `--benches`.
+#[derive(Debug, Clone, Args)]
+pub struct HarnessArgs {
+    /// Run only the test or bench with this name.
+    ///
+    /// libtest requires the exact name, like `parser::tests::parse_empty`,
+    /// while criterion accepts a regex.
+    #[clap(long)]
+    pub case: Option<String>,
+
+    /// Seconds to run a criterion bench for, with `--profile-time`.
+    #[clap(long, default_value_t = 10)]
+    pub profile_time: u64,
+}
+
+/// The harness of a built binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Harness {
+    /// Not a test or bench.
+    None,
+    /// The default harness. `bench` is set for the targets of benches.
+    Libtest { bench: bool },
+    /// `harness = false` with a dependency on `criterion`.
+    Criterion,
+    /// `harness = false` with an unknown harness.
+    Custom,
+}
+
+/// Detects the harness from the target of `bin` and the dependencies of its
+/// package.
+pub fn detect_harness(bin: &BinFile) -> Result<Harness> {
+    let md = run_cargo_metadata_no_deps_for(bin.manifest_path.clone())?;
+    let pkg = md
+        .packages
+        .iter()
+        .find(|pkg| pkg.id == bin.package_id)
+        .with_context(|| format!("failed to find the package `{}`", bin.package_id))?;
+
+    let kinds = pkg
+        .targets
+        .iter()
+        .find(|t| t.name == bin.crate_name)
+        .map(|t| t.kind.clone())
+        .unwrap_or_default();
+    let is_kind = |kind: &str| kinds.iter().any(|k| k == kind);
+
+    // The unit tests of a binary are built from the `bin` target with
+    // `profile.test` set.
+    if !bin.profile.test && !is_kind("test") && !is_kind("bench") {
+        return Ok(Harness::None);
+    }
+
+    if uses_libtest(pkg.manifest_path.as_std_path(), &bin.crate_name)? {
+        return Ok(Harness::Libtest {
+            bench: is_kind("bench"),
+        });
+    }
+
+    if pkg.dependencies.iter().any(|dep| dep.name == "criterion") {
+        Ok(Harness::Criterion)
+    } else {
+        Ok(Harness::Custom)
+    }
+}
+
+/// Returns false if the target is declared with `harness = false`.
+///
+/// `cargo metadata` does not report the harness, so the manifest is read.
+fn uses_libtest(manifest_path: &Path, target_name: &str) -> Result<bool> {
+    let content = std::fs::read_to_string(manifest_path)
+        .with_context(|| format!("failed to read {}", manifest_path.display()))?;
+    let toml = content
+        .parse::<DocumentMut>()
+        .with_context(|| format!("failed to parse {}", manifest_path.display()))?;
+
+    let harness = ["test", "bench", "bin"]
+        .into_iter()
+        .filter_map(|kind| toml.get(kind)?.as_array_of_tables())
+        .flat_map(|targets| targets.iter())
+        .find(|target| target.get("name").and_then(|v| v.as_str()) == Some(target_name))
+        .and_then(|target| target.get("harness")?.as_bool());
+
+    Ok(harness.unwrap_or(true))
+}
+
+impl HarnessArgs {
+    /// Returns the arguments to run `bin`, which are the arguments of the
+    /// harness followed by `args`.
+    pub fn args_for(&self, bin: &BinFile, args: &[String]) -> Result<Vec<String>> {
+        let harness = detect_harness(bin)?;
+
+        match harness {
+            Harness::None => {}
+            Harness::Custom if self.case.is_none() => {}
+            Harness::Custom => warn!(
+                "{} does not use libtest or criterion, so `--case` is passed as the first \
+                 argument",
+                bin.path.display()
+            ),
+            Harness::Libtest { .. } => {
+                info!("Running {} with the libtest harness", bin.crate_name)
+            }
+            Harness::Criterion => {
+                info!("Running {} with the criterion harness", bin.crate_name)
+            }
+        }
+
+        self.build_args(harness, args)
+    }
+
+    fn build_args(&self, harness: Harness, args: &[String]) -> Result<Vec<String>> {
+        let mut out = vec![];
+
+        // Flags already passed by the user are not repeated, as libtest
+        // rejects duplicates.
+        let flag = |out: &mut Vec<String>, name: &str, value: Option<String>| {
+            if args
+                .iter()
+                .any(|arg| arg == name || arg.starts_with(&format!("{}=", name)))
+            {
+                return;
+            }
+
+            out.push(name.to_string());
+            out.extend(value);
+        };
+
+        match harness {
+            Harness::None => {
+                if self.case.is_some() {
+                    bail!("`--case` can be used only with tests and benches")
+                }
+            }
+            Harness::Libtest { bench } => {
+                if let Some(case) = &self.case {
+                    out.push(case.clone());
+                    flag(&mut out, "--exact", None);
+                }
+                if bench {
+                    flag(&mut out, "--bench", None);
+                }
+                flag(&mut out, "--nocapture", None);
+                flag(&mut out, "--test-threads", Some("1".into()));
+            }
+            Harness::Criterion => {
+                flag(&mut out, "--bench", None);
+                out.extend(self.case.clone());
+                flag(
+                    &mut out,
+                    "--profile-time",
+                    Some(self.profile_time.to_string()),
+                );
+            }
+            Harness::Custom => {
+                out.extend(self.case.clone());
+            }
+        }
+
+        out.extend(args.iter().cloned());
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(case: Option<&str>) -> HarnessArgs {
+        HarnessArgs {
+            case: case.map(String::from),
+            profile_time: 5,
+        }
+    }
+
+    #[test]
+    fn harness_args() {
+        assert_eq!(
+            args(Some("parser::parse_empty"))
+                .build_args(Harness::Libtest { bench: false }, &[])
+                .unwrap(),
+            [
+                "parser::parse_empty",
+                "--exact",
+                "--nocapture",
+                "--test-threads",
+                "1"
+            ]
+        );
+        assert_eq!(
+            args(None)
+                .build_args(
+                    Harness::Libtest { bench: true },
+                    &["--test-threads".into(), "2".into()]
+                )
+                .unwrap(),
+            ["--bench", "--nocapture", "--test-threads", "2"]
+        );
+        assert_eq!(
+            args(Some("parse/large"))
+                .build_args(Harness::Criterion, &[])
+                .unwrap(),
+            ["--bench", "parse/large", "--profile-time", "5"]
+        );
+        assert_eq!(
+            args(Some("case"))
+                .build_args(Harness::Custom, &["-v".into()])
+                .unwrap(),
+            ["case", "-v"]
+        );
+        assert_eq!(
+            args(None)
+                .build_args(Harness::None, &["input.txt".into()])
+                .unwrap(),
+            ["input.txt"]
+        );
+        assert!(args(Some("case")).build_args(Harness::None, &[]).is_err());
+    }
+}
@@ -3,6 +3,8 @@ use std::path::Path;
 use anyhow::{bail, Result};
 
 pub mod cargo;
+pub mod harness;
+pub mod platform;
 
 pub fn open_file(filename: &Path) -> Result<()> {
     use std::process::Command;
@@ -0,0 +1,365 @@
+//! Platform-specific steps to prepare a built binary for profiling.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use object::{Architecture, Object, ObjectSection, ObjectSymbol, SymbolKind};
+use tracing::info;
+
+/// Options for the steps after the build.
+#[derive(Debug, Clone, Args)]
+pub struct PostBuildArgs {
+    /// Move the debug info to a separate `.debug` file using `objcopy`, which
+    /// is linked to the binary by `.gnu_debuglink`. Linux only.
+    ///
+    /// Binaries which are already split or have no debug info are left as-is.
+    #[clap(long)]
+    pub split_debug: bool,
+}
+
+/// A command to run on the built binary.
+#[derive(Debug)]
+pub struct Step {
+    pub description: String,
+    pub cmd: Command,
+}
+
+pub trait Platform {
+    /// Returns warnings about the binary, like missing debug info which makes
+    /// the profiles less useful.
+    fn check(&self, data: &[u8]) -> Vec<String>;
+
+    /// Returns the commands to run on the binary before profiling it. `data` is
+    /// the content of `bin`.
+    ///
+    /// `work_dir` is a temporary directory for the files used by the
+    /// commands.
+    fn steps(
+        &self,
+        bin: &Path,
+        data: &[u8],
+        args: &PostBuildArgs,
+        work_dir: &Path,
+    ) -> Result<Vec<Step>>;
+}
+
+/// Returns the platform of binaries built for `triple`.
+pub fn platform_for(triple: &str) -> Box<dyn Platform> {
+    if triple.contains("-apple-") {
+        Box::new(MacOs)
+    } else if triple.contains("-linux-") {
+        Box::new(Linux)
+    } else {
+        Box::new(Other)
+    }
+}
+
+pub struct Linux;
+
+impl Platform for Linux {
+    fn check(&self, data: &[u8]) -> Vec<String> {
+        let Ok(file) = object::File::parse(data) else {
+            return vec!["failed to parse the binary".into()];
+        };
+
+        let mut warnings = vec![];
+
+        let has_debug_info = file
+            .section_by_name(".debug_info")
+            .is_some_and(|s| s.size() > 0)
+            || file.section_by_name(".gnu_debuglink").is_some();
+        if !has_debug_info {
+            warnings.push(
+                "the binary has no debug info, so the profile will lack source locations; set \
+                 `debug = true` in the profile"
+                    .into(),
+            );
+        }
+
+        // Some functions of the precompiled libraries set up frame pointers
+        // anyway, so the ratio is around 20% without frame pointers and 90%
+        // with them.
+        if frame_pointer_ratio(&file).is_some_and(|ratio| ratio < 0.5) {
+            warnings.push(
+                "the binary seems to be built without frame pointers, so the call stacks may be \
+                 truncated; build with `--profiling-build`"
+                    .into(),
+            );
+        }
+
+        warnings
+    }
+
+    fn steps(&self, bin: &Path, data: &[u8], args: &PostBuildArgs, _: &Path) -> Result<Vec<Step>> {
+        if !args.split_debug {
+            return Ok(vec![]);
+        }
+
+        // The binary is modified in place, so it's already split if cargo did
+        // not relink it. Splitting it again would overwrite the `.debug` file
+        // with an empty one.
+        let file = object::File::parse(data).context("failed to parse the binary")?;
+        if file.section_by_name(".gnu_debuglink").is_some() {
+            info!("The debug info of {} is already split", bin.display());
+            return Ok(vec![]);
+        }
+        if file
+            .section_by_name(".debug_info")
+            .is_none_or(|s| s.size() == 0)
+        {
+            return Ok(vec![]);
+        }
+
+        let mut debug_file = bin.as_os_str().to_owned();
+        debug_file.push(".debug");
+        let debug_file = PathBuf::from(debug_file);
+
+        let mut keep_debug = Command::new("objcopy");
+        keep_debug
+            .arg("--only-keep-debug")
+            .arg(bin)
+            .arg(&debug_file);
+
+        let mut strip_debug = Command::new("objcopy");
+        strip_debug
+            .arg("--strip-debug")
+            .arg(format!("--add-gnu-debuglink={}", debug_file.display()))
+            .arg(bin);
+
+        Ok(vec![
+            Step {
+                description: format!("Writing the debug info to {}", debug_file.display()),
+                cmd: keep_debug,
+            },
+            Step {
+                description: "Removing the debug info from the binary".into(),
+                cmd: strip_debug,
+            },
+        ])
+    }
+}
+
+pub struct MacOs;
+
+const ENTITLEMENTS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+    <dict>
+        <key>com.apple.security.get-task-allow</key>
+        <true/>
+    </dict>
+</plist>
+"#;
+
+impl Platform for MacOs {
+    fn check(&self, _: &[u8]) -> Vec<String> {
+        vec![]
+    }
+
+    fn steps(&self, bin: &Path, _: &[u8], _: &PostBuildArgs, work_dir: &Path) -> Result<Vec<Step>> {
+        // Allows the profilers to attach to the process.
+        let plist = work_dir.join("entitlements.xml");
+        std::fs::write(&plist, ENTITLEMENTS).context("failed to write the entitlements file")?;
+
+        let mut codesign = Command::new("codesign");
+        codesign
+            .arg("-s")
+            .arg("-")
+            .arg("-v")
+            .arg("-f")
+            .arg("--entitlements")
+            .arg(&plist)
+            .arg(bin);
+
+        let mut dsymutil = Command::new("dsymutil");
+        dsymutil.arg(bin);
+
+        Ok(vec![
+            Step {
+                description: "Running codesign on the built binary".into(),
+                cmd: codesign,
+            },
+            Step {
+                description: "Running dsymutil on the built binary".into(),
+                cmd: dsymutil,
+            },
+        ])
+    }
+}
+
+/// Platforms without any preparation.
+pub struct Other;
+
+impl Platform for Other {
+    fn check(&self, _: &[u8]) -> Vec<String> {
+        vec![]
+    }
+
+    fn steps(&self, _: &Path, _: &[u8], _: &PostBuildArgs, _: &Path) -> Result<Vec<Step>> {
+        Ok(vec![])
+    }
+}
+
+/// Returns the ratio of functions which set up a frame pointer in the
+/// prologue, or [None] if the architecture is not supported.
+fn frame_pointer_ratio(file: &object::File) -> Option<f64> {
+    // `push rbp; mov rbp, rsp` and `mov x29, sp`, which follow instructions
+    // like `endbr64` or `stp x29, x30, [sp, #-16]!`.
+    let (prologue, window): (&[u8], usize) = match file.architecture() {
+        Architecture::X86_64 => (&[0x55, 0x48, 0x89, 0xe5], 16),
+        Architecture::Aarch64 => (&[0xfd, 0x03, 0x00, 0x91], 32),
+        _ => return None,
+    };
+
+    let mut total = 0;
+    let mut with_frame_pointer = 0;
+
+    for symbol in file.symbols() {
+        if symbol.kind() != SymbolKind::Text || symbol.size() < 16 {
+            continue;
+        }
+        let Some(section) = symbol
+            .section_index()
+            .and_then(|i| file.section_by_index(i).ok())
+        else {
+            continue;
+        };
+        let Ok(data) = section.data() else {
+            continue;
+        };
+
+        let Some(start) = symbol.address().checked_sub(section.address()) else {
+            continue;
+        };
+        let start = start as usize;
+        let end = (start + window).min(start + symbol.size() as usize);
+        let Some(code) = data.get(start..end.min(data.len())) else {
+            continue;
+        };
+
+        total += 1;
+        if code.windows(prologue.len()).any(|w| w == prologue) {
+            with_frame_pointer += 1;
+        }
+    }
+
+    if total == 0 {
+        return None;
+    }
+
+    Some(with_frame_pointer as f64 / total as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use object::{write, BinaryFormat, Endianness, SymbolFlags, SymbolScope};
+
+    use super::*;
+
+    fn fixture(frame_pointers: bool, debug_info: bool, debug_link: bool) -> Vec<u8> {
+        let mut obj =
+            write::Object::new(BinaryFormat::Elf, Architecture::X86_64, Endianness::Little);
+        let text = obj.section_id(write::StandardSection::Text);
+
+        if debug_info {
+            let section =
+                obj.add_section(vec![], b".debug_info".to_vec(), object::SectionKind::Debug);
+            obj.append_section_data(section, &[0; 100], 1);
+        }
+
+        if debug_link {
+            let section = obj.add_section(
+                vec![],
+                b".gnu_debuglink".to_vec(),
+                object::SectionKind::Other,
+            );
+            obj.append_section_data(section, b"app.debug\0\0\0\0\0\0\0", 4);
+        }
+
+        for i in 0..4 {
+            let mut code = vec![0xf3, 0x0f, 0x1e, 0xfa];
+            if frame_pointers {
+                code.extend([0x55, 0x48, 0x89, 0xe5]);
+            }
+            code.resize(32, 0x90);
+
+            let offset = obj.append_section_data(text, &code, 1);
+            obj.add_symbol(write::Symbol {
+                name: format!("f{}", i).into_bytes(),
+                value: offset,
+                size: code.len() as u64,
+                kind: SymbolKind::Text,
+                scope: SymbolScope::Linkage,
+                weak: false,
+                section: write::SymbolSection::Section(text),
+                flags: SymbolFlags::None,
+            });
+        }
+
+        obj.write().unwrap()
+    }
+
+    #[test]
+    fn linux_checks() {
+        assert!(Linux.check(&fixture(true, true, false)).is_empty());
+
+        let warnings = Linux.check(&fixture(false, false, false));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("debug info"));
+        assert!(warnings[1].contains("--profiling-build"));
+    }
+
+    fn describe(steps: &[Step]) -> Vec<String> {
+        steps
+            .iter()
+            .map(|step| {
+                let mut s = step.cmd.get_program().to_string_lossy().to_string();
+                for arg in step.cmd.get_args() {
+                    s.push(' ');
+                    s.push_str(&arg.to_string_lossy());
+                }
+                s
+            })
+            .collect()
+    }
+
+    #[test]
+    fn post_build_steps() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let bin = Path::new("target/release/app");
+        let data = fixture(true, true, false);
+
+        let split = PostBuildArgs { split_debug: true };
+        let no_split = PostBuildArgs { split_debug: false };
+
+        assert_eq!(
+            describe(&Linux.steps(bin, &data, &split, work_dir.path()).unwrap()),
+            [
+                "objcopy --only-keep-debug target/release/app target/release/app.debug",
+                "objcopy --strip-debug --add-gnu-debuglink=target/release/app.debug \
+                 target/release/app",
+            ]
+        );
+        assert!(Linux
+            .steps(bin, &data, &no_split, work_dir.path())
+            .unwrap()
+            .is_empty());
+
+        // Already split, or without debug info.
+        for data in [fixture(true, false, true), fixture(true, false, false)] {
+            assert!(Linux
+                .steps(bin, &data, &split, work_dir.path())
+                .unwrap()
+                .is_empty());
+        }
+
+        let steps = describe(&MacOs.steps(bin, &data, &no_split, work_dir.path()).unwrap());
+        assert!(steps[0].starts_with("codesign -s - -v -f --entitlements"));
+        assert_eq!(steps[1], "dsymutil target/release/app");
+        assert!(work_dir.path().join("entitlements.xml").is_file());
+    }
+}
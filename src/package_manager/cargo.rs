@@ -8,7 +8,10 @@ use serde::Deserialize;
 use super::{Dependency, PackageManager, PackageName, PackageVersion, Versions};
 
 #[derive(Debug, Default)]
-pub struct CargoPackageManager;
+pub struct CargoPackageManager {
+    /// Skip the yanked releases, which cannot be selected by new lockfiles.
+    pub skip_yanked: bool,
+}
 
 #[async_trait]
 impl PackageManager for CargoPackageManager {
@@ -34,7 +37,7 @@ impl PackageManager for CargoPackageManager {
                     }
                 };
 
-                if !constraints.matches(&line.vers) {
+                if (self.skip_yanked && line.yanked) || !constraints.matches(&line.vers) {
                     return None;
                 }
 
@@ -84,6 +87,8 @@ struct Descriptor {
     pub name: PackageName,
     pub vers: Version,
     pub deps: Vec<DepDescriptor>,
+    #[serde(default)]
+    pub yanked: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -1,17 +1,30 @@
 use std::{
     env,
-    io::BufReader,
-    path::PathBuf,
+    ffi::OsString,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     sync::Arc,
 };
 
 use anyhow::{bail, Context, Result};
 use cached::proc_macro::cached;
-use cargo_metadata::{ArtifactProfile, Message};
-use clap::Parser;
-use is_executable::IsExecutable;
-use tracing::info;
+use cargo_metadata::{ArtifactProfile, BuildScript, CargoOpt, DependencyKind, PackageId};
+use cargo_platform::Cfg;
+use clap::{Args, Parser};
+use rustc_hash::FxHashSet;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+use super::{
+    cargo_config::{build_target_triple, load_cargo_config_files, target_runner, CargoConfigFile},
+    cargo_messages::{BuildMessages, Progress},
+    dep_graph::DepGraph,
+};
 
 /// Built bin file.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -23,9 +36,15 @@ pub struct BinFile {
 
     pub crate_name: String,
     pub manifest_path: PathBuf,
+    pub package_id: PackageId,
+    /// The output of the build script of the package, which provides
+    /// `OUT_DIR` and the variables set by `cargo:rustc-env`.
+    pub build_script: Option<BuildScript>,
 }
 
-#[derive(Debug, Clone, Parser)]
+/// Also used in `ddt.toml`, with the same names as the command line options.
+#[derive(Debug, Clone, Default, Parser, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
 pub struct CargoBuildTarget {
     #[clap(long)]
     pub lib: bool,
@@ -58,131 +77,538 @@ pub struct CargoBuildTarget {
     pub features: Option<Vec<String>>,
 
     #[clap(long = "package", short = 'p')]
+    #[serde(rename = "package")]
     pub packages: Vec<String>,
 
     #[clap(long)]
     pub profile: Option<String>,
+
+    /// Target triple to build for.
+    #[clap(long)]
+    pub target: Option<String>,
+
+    #[clap(long)]
+    pub all_features: bool,
+
+    #[clap(long)]
+    pub no_default_features: bool,
+
+    #[clap(long)]
+    pub workspace: bool,
+
+    #[clap(long)]
+    pub manifest_path: Option<PathBuf>,
+
+    #[clap(long)]
+    pub target_dir: Option<PathBuf>,
+
+    #[clap(long)]
+    pub locked: bool,
+
+    #[clap(long)]
+    pub offline: bool,
+
+    /// Unstable flags passed to cargo, like `-Z build-std`.
+    #[clap(short = 'Z', value_name = "FLAG")]
+    #[serde(rename = "unstable")]
+    pub unstable_flags: Vec<String>,
 }
 
-/// Compile one or more targets.
-pub fn compile(config: &CargoBuildTarget) -> Result<Vec<BinFile>> {
-    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+/// The features to enable, with the same options as cargo.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Args)]
+pub struct FeatureArgs {
+    /// Features to activate.
+    #[clap(long)]
+    pub features: Option<Vec<String>>,
 
-    let mut cmd = Command::new(&cargo);
+    /// Activate all features of the selected packages.
+    #[clap(long)]
+    pub all_features: bool,
+
+    /// Do not activate the `default` feature.
+    #[clap(long)]
+    pub no_default_features: bool,
+}
+
+impl FeatureArgs {
+    /// Arguments for cargo commands like `cargo check`.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![];
+
+        if let Some(features) = &self.features {
+            args.push("--features".into());
+            args.push(features.join(","));
+        }
+        if self.all_features {
+            args.push("--all-features".into());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".into());
+        }
+
+        args
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Options for the JSON messages of the builds of a command.
+#[derive(Debug, Clone, Default, Args)]
+pub struct DiagnosticsArgs {
+    /// Write the JSON messages of cargo to this file, or to stdout if `-`, for
+    /// tools. The messages of all the builds of the command are written.
+    #[clap(long)]
+    pub diagnostics_json: Option<PathBuf>,
+}
+
+impl DiagnosticsArgs {
+    /// Opens the output once, so the builds of the command append to it.
+    pub async fn open(&self) -> Result<Option<DiagnosticsJson>> {
+        let out: Box<dyn AsyncWrite + Send + Unpin> = match &self.diagnostics_json {
+            Some(path) if path == Path::new("-") => Box::new(tokio::io::stdout()),
+            Some(path) => Box::new(BufWriter::new(
+                tokio::fs::File::create(path)
+                    .await
+                    .with_context(|| format!("failed to create {}", path.display()))?,
+            )),
+            None => return Ok(None),
+        };
+
+        Ok(Some(DiagnosticsJson(Mutex::new(out))))
+    }
+
+    /// Fails if the messages would be mixed with the output of the command on
+    /// stdout.
+    pub fn ensure_not_stdout(&self) -> Result<()> {
+        if self
+            .diagnostics_json
+            .as_deref()
+            .is_some_and(|path| path == Path::new("-"))
+        {
+            bail!(
+                "`--diagnostics-json -` can't be used with `--format json`, as both are printed \
+                 to stdout"
+            )
+        }
+
+        Ok(())
+    }
+}
 
-    cmd.arg("build");
+/// The output of the JSON messages, shared by the builds of a command.
+pub struct DiagnosticsJson(Mutex<Box<dyn AsyncWrite + Send + Unpin>>);
+
+impl DiagnosticsJson {
+    /// Concurrent builds are interleaved by line, so each line is still a
+    /// message.
+    async fn write_line(&self, line: &str) -> Result<()> {
+        let mut out = self.0.lock().await;
+
+        out.write_all(line.as_bytes())
+            .await
+            .context("failed to write the JSON messages")?;
+        out.write_all(b"\n")
+            .await
+            .context("failed to write the JSON messages")
+    }
 
-    if config.release {
-        cmd.arg("--release");
+    async fn flush(&self) -> Result<()> {
+        self.0
+            .lock()
+            .await
+            .flush()
+            .await
+            .context("failed to write the JSON messages")
     }
+}
 
-    // if !config.lib {
-    //     cmd.arg("--no-lib");
-    // }
+impl CargoBuildTarget {
+    /// Arguments for `cargo build`, `cargo rustc` and the like, to select the
+    /// same targets and options in every command.
+    pub fn cargo_args(&self) -> Vec<OsString> {
+        let mut args = Vec::<OsString>::new();
+
+        for (flag, enabled) in [
+            ("--release", self.release),
+            ("--lib", self.lib),
+            ("--benches", self.benches),
+            ("--tests", self.tests),
+            ("--examples", self.examples),
+            ("--all-features", self.all_features),
+            ("--no-default-features", self.no_default_features),
+            ("--workspace", self.workspace),
+            ("--locked", self.locked),
+            ("--offline", self.offline),
+        ] {
+            if enabled {
+                args.push(flag.into());
+            }
+        }
+
+        for (flag, value) in [
+            ("--bin", &self.bin),
+            ("--bench", &self.bench),
+            ("--test", &self.test),
+            ("--example", &self.example),
+            ("--profile", &self.profile),
+            ("--target", &self.target),
+        ] {
+            if let Some(value) = value {
+                args.push(flag.into());
+                args.push(value.into());
+            }
+        }
 
-    if config.benches {
-        cmd.arg("--benches");
+        if let Some(features) = &self.features {
+            args.push("--features".into());
+            args.push(features.join(",").into());
+        }
+
+        for pkg in &self.packages {
+            args.push("-p".into());
+            args.push(pkg.into());
+        }
+
+        for (flag, path) in [
+            ("--manifest-path", &self.manifest_path),
+            ("--target-dir", &self.target_dir),
+        ] {
+            if let Some(path) = path {
+                args.push(flag.into());
+                args.push(path.into());
+            }
+        }
+
+        for flag in &self.unstable_flags {
+            args.push("-Z".into());
+            args.push(flag.into());
+        }
+
+        args
     }
 
-    if let Some(target) = &config.bench {
-        cmd.arg("--bench").arg(target);
+    /// The feature selection of the build.
+    pub fn feature_args(&self) -> FeatureArgs {
+        FeatureArgs {
+            features: self.features.clone(),
+            all_features: self.all_features,
+            no_default_features: self.no_default_features,
+        }
     }
 
-    if config.tests {
-        cmd.arg("--tests");
+    /// Returns a copy which builds into `dir`, like `--target-dir`.
+    pub fn with_target_dir(&self, dir: PathBuf) -> Self {
+        Self {
+            target_dir: Some(dir),
+            ..self.clone()
+        }
     }
 
-    if let Some(target) = &config.test {
-        cmd.arg("--test").arg(target);
+    /// The target directory used by the build.
+    pub fn target_dir(&self) -> Result<PathBuf> {
+        if let Some(dir) = &self.target_dir {
+            return Ok(dir.clone());
+        }
+
+        match &self.manifest_path {
+            Some(manifest_path) => {
+                let md = cargo_metadata::MetadataCommand::new()
+                    .manifest_path(manifest_path)
+                    .no_deps()
+                    .exec()
+                    .context("cargo metadata failed")?;
+
+                Ok(md.target_directory.into())
+            }
+            None => cargo_target_dir(),
+        }
     }
-    if config.examples {
-        cmd.arg("--examples");
+
+    /// The name of the cargo profile used by the build.
+    pub fn profile_name(&self) -> &str {
+        match &self.profile {
+            Some(profile) => profile,
+            None if self.release => "release",
+            None => "dev",
+        }
     }
 
-    if let Some(target) = &config.example {
-        cmd.arg("--example").arg(target);
+    /// The environment variable which overrides `key` of the profile, like
+    /// `CARGO_PROFILE_RELEASE_OPT_LEVEL` for `opt-level`.
+    pub fn profile_env(&self, key: &str) -> String {
+        format!("CARGO_PROFILE_{}_{}", self.profile_name(), key)
+            .to_ascii_uppercase()
+            .replace('-', "_")
     }
 
-    if let Some(features) = &config.features {
-        cmd.arg("--features").arg(features.join(","));
+    /// The target triple given by `--target`, or configured by
+    /// `build.target` of `.cargo/config.toml`.
+    pub fn target_triple(&self) -> Result<Option<String>> {
+        if let Some(target) = &self.target {
+            return Ok(Some(target.clone()));
+        }
+
+        Ok(build_target_triple(&self.cargo_config_files()?))
+    }
+
+    /// The `.cargo/config.toml` files for the build, found from the directory
+    /// of `--manifest-path` or the current directory.
+    pub fn cargo_config_files(&self) -> Result<Vec<CargoConfigFile>> {
+        let cwd = env::current_dir().context("failed to get the current directory")?;
+
+        match self.manifest_path.as_deref().and_then(Path::parent) {
+            Some(dir) => load_cargo_config_files(&cwd.join(dir)),
+            None => load_cargo_config_files(&cwd),
+        }
     }
 
-    if let Some(profile) = &config.profile {
-        cmd.arg("--profile").arg(profile);
+    /// The directory of the artifacts in the target directory, like
+    /// `target/x86_64-unknown-linux-gnu/release`.
+    pub fn artifact_dir(&self) -> Result<PathBuf> {
+        let mut dir = self.target_dir()?;
+        if let Some(target) = self.target_triple()? {
+            dir.push(target);
+        }
+
+        dir.push(match self.profile_name() {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        });
+
+        Ok(dir)
     }
 
-    for pkg in &config.packages {
-        cmd.arg("-p").arg(pkg);
+    /// A directory for the outputs of ddt, like `target/flamegraph`, which is
+    /// separated by the target triple when cross-compiling.
+    pub fn output_dir(&self, name: &str) -> Result<PathBuf> {
+        let mut dir = self.target_dir()?;
+        if let Some(target) = self.target_triple()? {
+            dir.push(target);
+        }
+        dir.push(name);
+
+        Ok(dir)
     }
 
-    cmd.arg("--message-format=json");
+    /// The command used to run the built binaries, like `qemu-aarch64 -L
+    /// /usr/aarch64-linux-gnu`.
+    ///
+    /// Returns [None] if the binaries should be run directly, and an error if
+    /// the host cannot run them and no runner is configured.
+    pub fn runner(&self) -> Result<Option<Vec<String>>> {
+        let host = host_triple()?;
+        let triple = self.target_triple()?.unwrap_or_else(|| host.clone());
+
+        if let Some(runner) = target_runner(&self.cargo_config_files()?, &triple) {
+            return Ok(Some(runner));
+        }
+
+        if triple == host {
+            return Ok(None);
+        }
+
+        let host_cfgs = target_cfgs(host.clone())?;
+        let cfgs = target_cfgs(triple.clone())?;
+        if !can_run_natively(&host_cfgs, &cfgs) {
+            if arch_and_os(&host_cfgs).1 != arch_and_os(&cfgs).1 {
+                bail!(
+                    "the host ({}) cannot run binaries for `{}`; configure a runner like \
+                     `qemu-user` with `target.{}.runner` in `.cargo/config.toml`",
+                    host,
+                    triple,
+                    triple
+                )
+            }
+
+            // It may be emulated, like with `binfmt_misc`.
+            warn!(
+                "The host ({}) may not run binaries for `{}`. If they fail to start, configure \
+                 a runner like `qemu-user` with `target.{}.runner` in `.cargo/config.toml`",
+                host, triple, triple
+            );
+        }
+
+        Ok(None)
+    }
+}
+
+/// The target triple of the host, from `rustc -vV`.
+#[cached(result = true)]
+pub fn host_triple() -> Result<String> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let output = Command::new(&rustc)
+        .arg("-vV")
+        .output()
+        .with_context(|| format!("failed to run `{} -vV`", rustc))?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| host.trim().to_string())
+        .context("failed to find the host triple in the output of `rustc -vV`")
+}
+
+/// The `cfg` values of `triple`, like `unix` or `target_os = "linux"`, from
+/// `rustc --print cfg`.
+#[cached(result = true)]
+pub fn target_cfgs(triple: String) -> Result<Vec<Cfg>> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let output = Command::new(&rustc)
+        .arg("--print")
+        .arg("cfg")
+        .arg("--target")
+        .arg(&triple)
+        .output()
+        .with_context(|| format!("failed to run `{} --print cfg`", rustc))?;
+    if !output.status.success() {
+        bail!(
+            "`{} --print cfg --target {}` failed:\n{}",
+            rustc,
+            triple,
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            line.parse()
+                .with_context(|| format!("failed to parse the cfg `{}`", line))
+        })
+        .collect()
+}
+
+/// The `target_arch` and `target_os` of a target, from its [target_cfgs].
+fn arch_and_os(cfgs: &[Cfg]) -> (Option<&str>, Option<&str>) {
+    let value = |name: &str| {
+        cfgs.iter().find_map(|cfg| match cfg {
+            Cfg::KeyPair(key, value) if key == name => Some(value.as_str()),
+            _ => None,
+        })
+    };
+
+    (value("target_arch"), value("target_os"))
+}
+
+/// Returns true if the host runs binaries for the target, like
+/// `x86_64-unknown-linux-musl` or `i686-unknown-linux-gnu` on
+/// `x86_64-unknown-linux-gnu`. Both are given by their [target_cfgs].
+fn can_run_natively(host: &[Cfg], target: &[Cfg]) -> bool {
+    let (host_arch, host_os) = arch_and_os(host);
+    let (arch, os) = arch_and_os(target);
+
+    host_os == os
+        && match (host_arch, arch) {
+            (Some(host_arch), Some(arch)) if host_arch == arch => true,
+            // macOS dropped 32-bit binaries.
+            (Some("x86_64"), Some("x86")) => os != Some("macos"),
+            // Rosetta 2.
+            (Some("aarch64"), Some("x86_64")) => os == Some("macos"),
+            _ => false,
+        }
+}
+
+/// Compile one or more targets.
+///
+/// If `dir` is given, cargo runs in it. `envs` are extra environment variables
+/// like `CARGO_PROFILE_RELEASE_OPT_LEVEL`. The JSON messages of cargo are
+/// copied to `json_output`.
+///
+/// If `rustc_args` is not empty, the target is built with `cargo rustc`, which
+/// passes them to the compiler of the selected target only. A library may be
+/// built that way, so it's not an error if no binary is produced.
+///
+/// cargo is killed if the returned future is dropped, like on Ctrl-C in
+/// [crate::util::cancellable]. cargo stays in the foreground process group, so
+/// Ctrl-C also stops rustc and the build scripts.
+pub async fn compile_with(
+    config: &CargoBuildTarget,
+    dir: Option<&Path>,
+    envs: &[(String, String)],
+    json_output: Option<&DiagnosticsJson>,
+    rustc_args: &[OsString],
+) -> Result<Vec<BinFile>> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+
+    // Only used for the progress, so the build does not fail because of it.
+    let total = match count_crates(config, dir).await {
+        Ok(total) => Some(total),
+        Err(err) => {
+            info!("failed to count the crates to compile: {:?}", err);
+            None
+        }
+    };
+
+    let mut cmd = Command::new(&cargo);
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
+    cmd.envs(envs.iter().map(|(k, v)| (k, v)));
+
+    // The progress of cargo is replaced by ours.
+    cmd.arg(if rustc_args.is_empty() {
+        "build"
+    } else {
+        "rustc"
+    });
+    cmd.arg("--quiet");
+    cmd.args(config.cargo_args());
+    if io::stderr().is_terminal() {
+        cmd.arg("--message-format=json-diagnostic-rendered-ansi");
+    } else {
+        cmd.arg("--message-format=json");
+    }
+    if !rustc_args.is_empty() {
+        cmd.arg("--").args(rustc_args);
+    }
 
     let cmd_str = format!("{:?}", cmd);
 
-    let mut child = cmd
+    let mut child = tokio::process::Command::from(cmd)
+        .kill_on_drop(true)
         .stdout(Stdio::piped())
         .stderr(Stdio::inherit())
         .spawn()
         .with_context(|| format!("failed to spawn cargo\n{}", cmd_str))?;
 
-    let mut binaries = vec![];
-    let reader = BufReader::new(child.stdout.take().unwrap());
-    for message in Message::parse_stream(reader) {
-        match message.unwrap() {
-            Message::CompilerMessage(..) => {}
-            Message::CompilerArtifact(mut artifact) => {
-                if artifact.target.kind.contains(&"bin".to_string())
-                    || artifact.target.kind.contains(&"test".to_string())
-                    || artifact.target.kind.contains(&"bench".to_string())
-                    || artifact.target.kind.contains(&"example".to_string())
-                {
-                    let mut executable = None;
-
-                    artifact.filenames.retain(|path| {
-                        if executable.is_none() && PathBuf::from(path).is_executable() {
-                            executable = Some(path.clone());
-                            return false;
-                        }
-
-                        true
-                    });
-
-                    binaries.push(BinFile {
-                        path: match executable {
-                            Some(v) => v.into(),
-                            None => continue,
-                        },
-                        extra_files: artifact.filenames.into_iter().map(From::from).collect(),
-                        profile: artifact.profile,
-                        manifest_path: artifact.manifest_path.into(),
-                        crate_name: artifact.target.name,
-                    });
-                    continue;
-                }
-
-                if artifact.target.kind == vec!["lib".to_string()] {
-                    continue;
-                }
-                // println!("{:?}", artifact);
-            }
-            Message::BuildScriptExecuted(_script) => {
-                // eprintln!("Executed build script of `{}`",
-                // script.package_id.repr);
-            }
-            Message::BuildFinished(finished) => {
-                if !finished.success {
-                    bail!("Failed to compile binary using cargo\n{}", cmd_str)
-                }
-            }
-            _ => (),
+    let mut messages = BuildMessages::new(Progress::new(total, io::stderr().is_terminal()));
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .context("failed to read the output of cargo")?
+    {
+        if let Some(out) = json_output {
+            out.write_line(&line).await?;
         }
+
+        messages.handle_line(line);
+    }
+
+    if let Some(out) = json_output {
+        out.flush().await?;
     }
 
     let _output = child
         .wait()
+        .await
         .with_context(|| format!("Couldn't get cargo's exit status\n{}", cmd_str))?;
 
-    if binaries.is_empty() {
+    messages.progress.clear();
+    info!("{} crates compiled", messages.progress.count());
+
+    // After cargo exits, so the summary is not mixed with the output of cargo.
+    messages.diagnostics.print();
+
+    if !messages.success {
+        bail!("Failed to compile binary using cargo\n{}", cmd_str)
+    }
+
+    let mut binaries = messages.binaries;
+    if binaries.is_empty() && rustc_args.is_empty() {
         bail!("cargo did not produce any useful binary\n{}", cmd_str)
     }
 
@@ -193,6 +619,91 @@ pub fn compile(config: &CargoBuildTarget) -> Result<Vec<BinFile>> {
     Ok(binaries)
 }
 
+/// Estimates the number of crates in the build, from the dependency graph of
+/// the selected packages.
+async fn count_crates(config: &CargoBuildTarget, dir: Option<&Path>) -> Result<usize> {
+    let mut cmd = cargo_metadata::MetadataCommand::new();
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
+    if let Some(path) = &config.manifest_path {
+        cmd.manifest_path(path);
+    }
+    if config.all_features {
+        cmd.features(CargoOpt::AllFeatures);
+    }
+    if config.no_default_features {
+        cmd.features(CargoOpt::NoDefaultFeatures);
+    }
+    if let Some(features) = &config.features {
+        cmd.features(CargoOpt::SomeFeatures(features.clone()));
+    }
+    if let Some(triple) = config.target_triple()? {
+        cmd.other_options(vec!["--filter-platform".into(), triple]);
+    }
+
+    let output = tokio::process::Command::from(cmd.cargo_command())
+        .kill_on_drop(true)
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .context("failed to run cargo metadata")?;
+    if !output.status.success() {
+        bail!("cargo metadata failed with {}", output.status)
+    }
+
+    let md = cargo_metadata::MetadataCommand::parse(
+        String::from_utf8(output.stdout).context("cargo metadata printed invalid utf-8")?,
+    )?;
+    let graph = DepGraph::new(&md)?;
+
+    let roots = if !config.packages.is_empty() {
+        md.workspace_members
+            .iter()
+            .filter(|id| config.packages.contains(&graph.package(id).name))
+            .collect::<Vec<_>>()
+    } else if let Some(root) = md.resolve.as_ref().and_then(|r| r.root.as_ref()) {
+        if config.workspace {
+            md.workspace_members.iter().collect()
+        } else {
+            vec![root]
+        }
+    } else {
+        // A virtual manifest.
+        md.workspace_members.iter().collect()
+    };
+
+    // The dev-dependencies are built only for the tests, benches and examples
+    // of the selected packages.
+    let dev = config.tests
+        || config.benches
+        || config.examples
+        || config.test.is_some()
+        || config.bench.is_some()
+        || config.example.is_some();
+
+    let mut seen = roots.iter().copied().collect::<FxHashSet<_>>();
+    let mut queue = roots.clone();
+    while let Some(id) = queue.pop() {
+        let is_root = roots.contains(&id);
+
+        for edge in graph.edges_from(id) {
+            let needed = edge.dep.dep_kinds.is_empty()
+                || edge
+                    .dep
+                    .dep_kinds
+                    .iter()
+                    .any(|k| k.kind != DependencyKind::Development || (dev && is_root));
+
+            if needed && seen.insert(edge.to) {
+                queue.push(edge.to);
+            }
+        }
+    }
+
+    Ok(seen.len())
+}
+
 #[cached(result = true)]
 pub fn run_cargo_metadata_no_deps() -> Result<Arc<cargo_metadata::Metadata>> {
     let md = cargo_metadata::MetadataCommand::new()
@@ -203,6 +714,21 @@ pub fn run_cargo_metadata_no_deps() -> Result<Arc<cargo_metadata::Metadata>> {
     Ok(Arc::new(md))
 }
 
+/// Same as [run_cargo_metadata_no_deps], but for the workspace of
+/// `manifest_path`.
+#[cached(result = true)]
+pub fn run_cargo_metadata_no_deps_for(
+    manifest_path: PathBuf,
+) -> Result<Arc<cargo_metadata::Metadata>> {
+    let md = cargo_metadata::MetadataCommand::new()
+        .manifest_path(&manifest_path)
+        .no_deps()
+        .exec()
+        .context("cargo metadata failed")?;
+
+    Ok(Arc::new(md))
+}
+
 #[cached(result = true)]
 pub fn run_cargo_metadata_with_deps() -> Result<Arc<cargo_metadata::Metadata>> {
     let md = cargo_metadata::MetadataCommand::new()
@@ -212,6 +738,38 @@ pub fn run_cargo_metadata_with_deps() -> Result<Arc<cargo_metadata::Metadata>> {
     Ok(Arc::new(md))
 }
 
+/// Same as [run_cargo_metadata_with_deps], but the dependency graph is
+/// resolved only for the given features and target triple.
+#[cached(result = true)]
+pub fn run_cargo_metadata_with_deps_for(
+    features: FeatureArgs,
+    target: Option<String>,
+) -> Result<Arc<cargo_metadata::Metadata>> {
+    if features.is_default() && target.is_none() {
+        return run_cargo_metadata_with_deps();
+    }
+
+    let mut cmd = cargo_metadata::MetadataCommand::new();
+
+    if let Some(features) = features.features {
+        cmd.features(CargoOpt::SomeFeatures(features));
+    }
+    if features.all_features {
+        cmd.features(CargoOpt::AllFeatures);
+    }
+    if features.no_default_features {
+        cmd.features(CargoOpt::NoDefaultFeatures);
+    }
+
+    if let Some(target) = target {
+        cmd.other_options(vec!["--filter-platform".to_string(), target]);
+    }
+
+    let md = cmd.exec().context("cargo metadata failed")?;
+
+    Ok(Arc::new(md))
+}
+
 pub fn cargo_target_dir() -> Result<PathBuf> {
     let md = run_cargo_metadata_no_deps()?;
 
@@ -227,3 +785,102 @@ pub fn cargo_workspace_dir() -> Result<PathBuf> {
 pub fn cargo_root_manifest() -> Result<PathBuf> {
     Ok(cargo_workspace_dir()?.join("Cargo.toml"))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profile_env_of_build_target() {
+        let env = |args: &[&str]| {
+            CargoBuildTarget::parse_from(["ddt"].iter().chain(args)).profile_env("opt-level")
+        };
+
+        assert_eq!(env(&[]), "CARGO_PROFILE_DEV_OPT_LEVEL");
+        assert_eq!(env(&["--release"]), "CARGO_PROFILE_RELEASE_OPT_LEVEL");
+        assert_eq!(
+            env(&["--profile", "release-lto"]),
+            "CARGO_PROFILE_RELEASE_LTO_OPT_LEVEL"
+        );
+    }
+
+    #[test]
+    fn cargo_args_of_build_target() {
+        let build_target = CargoBuildTarget::parse_from([
+            "ddt",
+            "--release",
+            "--bin",
+            "app",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--no-default-features",
+            "--features",
+            "a",
+            "--features",
+            "b",
+            "-p",
+            "app",
+            "--target-dir",
+            "out",
+            "--locked",
+            "-Z",
+            "build-std",
+        ]);
+
+        assert_eq!(
+            build_target.cargo_args(),
+            [
+                "--release",
+                "--no-default-features",
+                "--locked",
+                "--bin",
+                "app",
+                "--target",
+                "aarch64-unknown-linux-gnu",
+                "--features",
+                "a,b",
+                "-p",
+                "app",
+                "--target-dir",
+                "out",
+                "-Z",
+                "build-std",
+            ]
+        );
+        assert_eq!(
+            build_target.artifact_dir().unwrap(),
+            Path::new("out/aarch64-unknown-linux-gnu/release")
+        );
+    }
+
+    /// The cfgs of a target with `arch` and `os`, as printed by rustc.
+    fn cfgs(arch: &str, os: &str) -> Vec<Cfg> {
+        vec![
+            "unix".parse().unwrap(),
+            format!("target_arch=\"{}\"", arch).parse().unwrap(),
+            format!("target_os=\"{}\"", os).parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn run_natively() {
+        let host = cfgs("x86_64", "linux");
+
+        assert!(can_run_natively(&host, &host));
+        assert!(!can_run_natively(&host, &cfgs("aarch64", "linux")));
+        assert!(!can_run_natively(&host, &cfgs("x86_64", "windows")));
+        assert!(!can_run_natively(&host, &cfgs("wasm32", "unknown")));
+        assert!(can_run_natively(&host, &cfgs("x86", "linux")));
+        assert!(!can_run_natively(&host, &cfgs("x86", "windows")));
+        // Like `aarch64-linux-android`, which has no vendor.
+        assert!(!can_run_natively(&host, &cfgs("aarch64", "android")));
+
+        let mac = cfgs("aarch64", "macos");
+        assert!(can_run_natively(&mac, &cfgs("x86_64", "macos")));
+        assert!(!can_run_natively(
+            &cfgs("x86_64", "macos"),
+            &cfgs("x86", "macos")
+        ));
+        assert!(!can_run_natively(&mac, &cfgs("x86_64", "linux")));
+    }
+}
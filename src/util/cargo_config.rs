@@ -0,0 +1,392 @@
+//! Reads the settings of `.cargo/config.toml` which affect where the artifacts
+//! are and how they are run.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use cargo_platform::{Cfg, CfgExpr};
+use toml_edit::{DocumentMut, Item};
+
+/// A parsed `.cargo/config.toml`.
+#[derive(Debug)]
+pub struct CargoConfigFile {
+    pub path: PathBuf,
+    pub toml: DocumentMut,
+}
+
+/// Loads the config files which apply to `cwd`, with the highest priority
+/// first.
+///
+/// Like cargo, the `.cargo` directories of the ancestors of `cwd` are
+/// searched, and then `$CARGO_HOME`.
+pub fn load_cargo_config_files(cwd: &Path) -> Result<Vec<CargoConfigFile>> {
+    let mut dirs = cwd
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect::<Vec<_>>();
+    if let Some(cargo_home) = cargo_home() {
+        if !dirs.contains(&cargo_home) {
+            dirs.push(cargo_home);
+        }
+    }
+
+    let mut files = vec![];
+    for dir in dirs {
+        // `config` is the legacy name, and is used only if `config.toml` does
+        // not exist.
+        let Some(path) = ["config.toml", "config"]
+            .into_iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+        else {
+            continue;
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let toml = content
+            .parse::<DocumentMut>()
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        files.push(CargoConfigFile { path, toml });
+    }
+
+    Ok(files)
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("CARGO_HOME") {
+        return Some(dir.into());
+    }
+
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".cargo"))
+}
+
+/// Returns the first value of `key` (like `build.target`) in the config
+/// files.
+fn get<'a>(files: &'a [CargoConfigFile], key: &[&str]) -> Option<(&'a CargoConfigFile, &'a Item)> {
+    files.iter().find_map(|file| {
+        let mut item = file.toml.as_item();
+        for k in key {
+            item = item.get(k)?;
+        }
+
+        Some((file, item))
+    })
+}
+
+/// Converts `"a b"` or `["a", "b"]` to a command line, like cargo does for
+/// `runner`.
+fn string_or_array(item: &Item) -> Option<Vec<String>> {
+    if let Some(s) = item.as_str() {
+        return Some(s.split_whitespace().map(String::from).collect());
+    }
+
+    item.as_array()?
+        .iter()
+        .map(|v| v.as_str().map(String::from))
+        .collect()
+}
+
+/// The default target triple from `CARGO_BUILD_TARGET` or `build.target`.
+pub fn build_target_triple(files: &[CargoConfigFile]) -> Option<String> {
+    if let Ok(target) = env::var("CARGO_BUILD_TARGET") {
+        return Some(target);
+    }
+
+    let (_, item) = get(files, &["build", "target"])?;
+    item.as_str().map(String::from)
+}
+
+/// The runner for `triple`, from `CARGO_TARGET_<triple>_RUNNER` or
+/// `target.<triple>.runner`.
+pub fn target_runner(files: &[CargoConfigFile], triple: &str) -> Option<Vec<String>> {
+    let env_name = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        triple.to_ascii_uppercase().replace(['-', '.'], "_")
+    );
+    if let Ok(runner) = env::var(env_name) {
+        return Some(runner.split_whitespace().map(String::from).collect());
+    }
+
+    let (file, item) = get(files, &["target", triple, "runner"])?;
+    let mut runner = string_or_array(item)?;
+
+    // Like cargo, a relative path with a slash is relative to the parent of
+    // the `.cargo` directory.
+    if let Some(program) = runner.first_mut() {
+        if program.contains('/') && Path::new(program).is_relative() {
+            if let Some(root) = file.path.parent().and_then(|dir| dir.parent()) {
+                *program = root.join(&*program).to_string_lossy().to_string();
+            }
+        }
+    }
+
+    Some(runner).filter(|runner| !runner.is_empty())
+}
+
+/// The rustflags cargo would use for `triple`, from the environment variables
+/// or `.cargo/config.toml`. `cfgs` are the `cfg` values of `triple`.
+pub fn rustflags(files: &[CargoConfigFile], triple: &str, cfgs: &[Cfg]) -> Vec<String> {
+    if let Ok(flags) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+        return flags
+            .split('\x1f')
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect();
+    }
+
+    if let Ok(flags) = env::var("RUSTFLAGS") {
+        return flags.split_whitespace().map(String::from).collect();
+    }
+
+    config_rustflags(files, triple, cfgs)
+}
+
+/// Like cargo, the flags of `target.<triple>` and the matching
+/// `target.'cfg(..)'` tables are joined, and `build.rustflags` is used only if
+/// none of them is set.
+fn config_rustflags(files: &[CargoConfigFile], triple: &str, cfgs: &[Cfg]) -> Vec<String> {
+    let mut flags = None::<Vec<String>>;
+
+    for file in files {
+        let Some(targets) = file
+            .toml
+            .get("target")
+            .and_then(|item| item.as_table_like())
+        else {
+            continue;
+        };
+
+        for (key, item) in targets.iter() {
+            if key != triple && !CfgExpr::matches_key(key, cfgs) {
+                continue;
+            }
+
+            if let Some(value) = item.get("rustflags").and_then(string_or_array) {
+                flags.get_or_insert_with(Vec::new).extend(value);
+            }
+        }
+    }
+
+    flags
+        .or_else(|| get(files, &["build", "rustflags"]).and_then(|(_, item)| string_or_array(item)))
+        .unwrap_or_default()
+}
+
+/// The variables of the `[env]` tables, like cargo sets for `cargo run` and
+/// `cargo test`.
+///
+/// `lookup` returns the current value of a variable, which is kept unless
+/// the entry has `force = true`.
+pub fn config_env(
+    files: &[CargoConfigFile],
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, String)> {
+    let mut envs = vec![];
+    // Like cargo, an entry overrides the whole entry of the files with lower
+    // priority, even if it's not applied.
+    let mut seen = vec![];
+
+    for file in files {
+        let Some(table) = file.toml.get("env").and_then(|item| item.as_table_like()) else {
+            continue;
+        };
+
+        for (key, item) in table.iter() {
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+
+            // `KEY = "value"` or `KEY = { value = "...", force = true, relative = true }`
+            let (value, force, relative) = match item.as_str() {
+                Some(value) => (value, false, false),
+                None => {
+                    let Some(table) = item.as_table_like() else {
+                        continue;
+                    };
+                    let Some(value) = table.get("value").and_then(|v| v.as_str()) else {
+                        continue;
+                    };
+                    let flag = |name| table.get(name).and_then(|v| v.as_bool()).unwrap_or(false);
+                    (value, flag("force"), flag("relative"))
+                }
+            };
+
+            if !force && lookup(key).is_some() {
+                continue;
+            }
+
+            // Relative to the parent of the `.cargo` directory.
+            let value = match file.path.parent().and_then(|dir| dir.parent()) {
+                Some(root) if relative => root.join(value).to_string_lossy().to_string(),
+                _ => value.to_string(),
+            };
+
+            envs.push((key.to_string(), value));
+        }
+    }
+
+    envs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runner_from_config() {
+        let files = vec![
+            CargoConfigFile {
+                path: PathBuf::from("/work/app/.cargo/config.toml"),
+                toml: r#"
+[target.aarch64-unknown-linux-gnu]
+runner = "qemu-aarch64 -L /usr/aarch64-linux-gnu"
+
+[target.riscv64gc-unknown-linux-gnu]
+runner = ["./scripts/run.sh", "--riscv"]
+"#
+                .parse()
+                .unwrap(),
+            },
+            CargoConfigFile {
+                path: PathBuf::from("/home/me/.cargo/config.toml"),
+                toml: r#"
+[build]
+target = "aarch64-unknown-linux-gnu"
+
+[target.aarch64-unknown-linux-gnu]
+runner = "ignored"
+"#
+                .parse()
+                .unwrap(),
+            },
+        ];
+
+        assert_eq!(
+            target_runner(&files, "aarch64-unknown-linux-gnu"),
+            Some(vec![
+                "qemu-aarch64".to_string(),
+                "-L".to_string(),
+                "/usr/aarch64-linux-gnu".to_string()
+            ])
+        );
+        assert_eq!(
+            target_runner(&files, "riscv64gc-unknown-linux-gnu"),
+            Some(vec![
+                "/work/app/./scripts/run.sh".to_string(),
+                "--riscv".to_string()
+            ])
+        );
+        assert_eq!(target_runner(&files, "x86_64-unknown-linux-gnu"), None);
+    }
+
+    #[test]
+    fn env_from_config() {
+        let files = vec![
+            CargoConfigFile {
+                path: PathBuf::from("/work/app/.cargo/config.toml"),
+                toml: r#"
+[env]
+RUST_LOG = "debug"
+FIXTURES = { value = "tests/fixtures", relative = true }
+HOME = { value = "/tmp/home", force = true }
+LANG = "en_US.UTF-8"
+"#
+                .parse()
+                .unwrap(),
+            },
+            CargoConfigFile {
+                path: PathBuf::from("/home/me/.cargo/config.toml"),
+                toml: r#"
+[env]
+RUST_LOG = "ignored"
+USER = "me"
+LANG = { value = "C", force = true }
+"#
+                .parse()
+                .unwrap(),
+            },
+        ];
+
+        let lookup = |key: &str| matches!(key, "HOME" | "LANG").then(|| "set".to_string());
+
+        assert_eq!(
+            config_env(&files, lookup),
+            vec![
+                ("RUST_LOG".to_string(), "debug".to_string()),
+                (
+                    "FIXTURES".to_string(),
+                    "/work/app/tests/fixtures".to_string()
+                ),
+                ("HOME".to_string(), "/tmp/home".to_string()),
+                ("USER".to_string(), "me".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rustflags_from_config() {
+        let files = vec![
+            CargoConfigFile {
+                path: PathBuf::from("/work/app/.cargo/config.toml"),
+                toml: r#"
+[build]
+rustflags = ["-Cdebuginfo=1"]
+
+[target.x86_64-unknown-linux-gnu]
+rustflags = ["-Ctarget-cpu=native"]
+
+[target.'cfg(all(target_os = "linux", not(target_env = "musl")))']
+rustflags = "-Clink-arg=-fuse-ld=lld"
+"#
+                .parse()
+                .unwrap(),
+            },
+            CargoConfigFile {
+                path: PathBuf::from("/home/me/.cargo/config.toml"),
+                toml: r#"
+[target.'cfg(unix)']
+rustflags = ["-Cforce-frame-pointers=yes"]
+
+[target.'cfg(windows)']
+rustflags = ["-Ctarget-feature=+crt-static"]
+"#
+                .parse()
+                .unwrap(),
+            },
+        ];
+
+        let cfgs = |cfgs: &[&str]| {
+            cfgs.iter()
+                .map(|c| c.parse().unwrap())
+                .collect::<Vec<Cfg>>()
+        };
+        let gnu = cfgs(&["unix", "target_os=\"linux\"", "target_env=\"gnu\""]);
+        let musl = cfgs(&["unix", "target_os=\"linux\"", "target_env=\"musl\""]);
+        let wasm = cfgs(&["target_family=\"wasm\""]);
+
+        assert_eq!(
+            config_rustflags(&files, "x86_64-unknown-linux-gnu", &gnu),
+            [
+                "-Ctarget-cpu=native",
+                "-Clink-arg=-fuse-ld=lld",
+                "-Cforce-frame-pointers=yes"
+            ]
+        );
+        assert_eq!(
+            config_rustflags(&files, "x86_64-unknown-linux-musl", &musl),
+            ["-Cforce-frame-pointers=yes"]
+        );
+        assert_eq!(
+            config_rustflags(&files, "wasm32-unknown-unknown", &wasm),
+            ["-Cdebuginfo=1"]
+        );
+    }
+}
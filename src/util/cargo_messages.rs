@@ -0,0 +1,352 @@
+//! Handles the JSON messages of `cargo build` as they are printed.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use cargo_metadata::{
+    diagnostic::DiagnosticLevel, BuildScript, CompilerMessage, Message, PackageId,
+};
+use is_executable::IsExecutable;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::cargo_build::BinFile;
+
+/// The state of a build, updated with each line printed by cargo.
+#[derive(Debug)]
+pub(super) struct BuildMessages {
+    pub binaries: Vec<BinFile>,
+    pub diagnostics: DiagnosticSummary,
+    pub progress: Progress,
+    pub success: bool,
+    build_scripts: FxHashMap<PackageId, BuildScript>,
+}
+
+impl BuildMessages {
+    pub fn new(progress: Progress) -> Self {
+        Self {
+            binaries: vec![],
+            diagnostics: Default::default(),
+            progress,
+            success: false,
+            build_scripts: Default::default(),
+        }
+    }
+
+    pub fn handle_line(&mut self, line: String) {
+        let message = serde_json::from_str::<Message>(&line).unwrap_or(Message::TextLine(line));
+
+        match message {
+            Message::CompilerMessage(msg) => {
+                self.progress.clear();
+                self.diagnostics.add(&msg);
+            }
+            Message::CompilerArtifact(mut artifact) => {
+                self.progress
+                    .compiled(&artifact.package_id, &artifact.target.name);
+
+                if !artifact
+                    .target
+                    .kind
+                    .iter()
+                    .any(|kind| matches!(kind.as_str(), "bin" | "test" | "bench" | "example"))
+                {
+                    return;
+                }
+
+                let mut executable = None;
+
+                artifact.filenames.retain(|path| {
+                    if executable.is_none() && PathBuf::from(path).is_executable() {
+                        executable = Some(path.clone());
+                        return false;
+                    }
+
+                    true
+                });
+
+                let Some(executable) = executable else {
+                    return;
+                };
+
+                self.binaries.push(BinFile {
+                    path: executable.into(),
+                    extra_files: artifact.filenames.into_iter().map(From::from).collect(),
+                    profile: artifact.profile,
+                    manifest_path: artifact.manifest_path.into(),
+                    crate_name: artifact.target.name,
+                    build_script: self.build_scripts.get(&artifact.package_id).cloned(),
+                    package_id: artifact.package_id,
+                });
+            }
+            Message::BuildScriptExecuted(script) => {
+                // Build scripts run before the targets of the package are
+                // compiled.
+                self.build_scripts.insert(script.package_id.clone(), script);
+            }
+            Message::BuildFinished(finished) => {
+                self.success = finished.success;
+            }
+            Message::TextLine(line) => {
+                // Not a JSON message, like the output of build scripts.
+                self.progress.clear();
+                eprintln!("{}", line);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Reports the number of compiled crates, as `N/M crates compiled`.
+///
+/// If `redraw` is set, it's a single line of stderr which is updated for each
+/// crate. Otherwise a line is printed for each crate.
+#[derive(Debug, Default)]
+pub(super) struct Progress {
+    /// Estimated number of crates in the build.
+    total: Option<usize>,
+    compiled: FxHashSet<PackageId>,
+    redraw: bool,
+    drawn: bool,
+}
+
+impl Progress {
+    pub fn new(total: Option<usize>, redraw: bool) -> Self {
+        Self {
+            total,
+            redraw,
+            ..Default::default()
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.compiled.len()
+    }
+
+    fn compiled(&mut self, id: &PackageId, name: &str) {
+        // A package has an artifact for each target, and another for the build
+        // script.
+        if !self.compiled.insert(id.clone()) {
+            return;
+        }
+
+        if self.redraw {
+            eprint!("\r\x1b[2K{}", self.line(name));
+            self.drawn = true;
+        } else {
+            eprintln!("{}", self.line(name));
+        }
+    }
+
+    fn line(&self, name: &str) -> String {
+        let count = self.count();
+        match self.total {
+            // The estimate may be smaller than the real number.
+            Some(total) => format!("{}/{} crates compiled: {}", count, total.max(count), name),
+            None => format!("{} crates compiled: {}", count, name),
+        }
+    }
+
+    /// Clears the progress line, so the next output is not mixed with it.
+    pub fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
+}
+
+/// Streams the diagnostics of rustc to stderr, and counts them per crate.
+#[derive(Debug, Default)]
+pub(super) struct DiagnosticSummary {
+    /// Crate name to the number of warnings and errors.
+    counts: BTreeMap<String, (usize, usize)>,
+}
+
+impl DiagnosticSummary {
+    fn add(&mut self, msg: &CompilerMessage) {
+        if let Some(rendered) = &msg.message.rendered {
+            eprint!("{}", rendered);
+        }
+
+        let counts = self.counts.entry(msg.target.name.clone()).or_default();
+        match msg.message.level {
+            DiagnosticLevel::Warning => counts.0 += 1,
+            DiagnosticLevel::Error | DiagnosticLevel::Ice => counts.1 += 1,
+            _ => {}
+        }
+    }
+
+    pub fn print(&self) {
+        for line in self.lines() {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+
+        for (name, (warnings, errors)) in &self.counts {
+            let mut parts = vec![];
+            if *warnings > 0 {
+                parts.push(plural(*warnings, "warning"));
+            }
+            if *errors > 0 {
+                parts.push(plural(*errors, "error"));
+            }
+
+            if !parts.is_empty() {
+                lines.push(format!("`{}` generated {}", name, parts.join(" and ")));
+            }
+        }
+
+        lines
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("1 {}", word)
+    } else {
+        format!("{} {}s", n, word)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn target(name: &str, kind: &str) -> serde_json::Value {
+        json!({
+            "name": name,
+            "kind": [kind],
+            "crate_types": [kind],
+            "required-features": [],
+            "src_path": "/work/app/src/main.rs",
+            "edition": "2021",
+            "doctest": false,
+            "test": true,
+            "doc": true
+        })
+    }
+
+    fn artifact(package_id: &str, name: &str, kind: &str, filenames: Vec<String>) -> String {
+        json!({
+            "reason": "compiler-artifact",
+            "package_id": package_id,
+            "manifest_path": "/work/app/Cargo.toml",
+            "target": target(name, kind),
+            "profile": {
+                "opt_level": "3",
+                "debuginfo": 0,
+                "debug_assertions": false,
+                "overflow_checks": false,
+                "test": false
+            },
+            "features": [],
+            "filenames": filenames,
+            "executable": null,
+            "fresh": true
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn handle_build_messages() {
+        let exe = std::env::current_exe()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let app = "app 0.1.0 (path+file:///work/app)";
+        let regex = "regex 1.11.1 (registry+https://github.com/rust-lang/crates.io-index)";
+
+        let mut messages = BuildMessages::new(Progress::new(Some(1), false));
+        for line in [
+            artifact(
+                regex,
+                "regex",
+                "lib",
+                vec!["/work/app/target/libregex.rlib".into()],
+            ),
+            json!({
+                "reason": "build-script-executed",
+                "package_id": app,
+                "linked_libs": [],
+                "linked_paths": [],
+                "cfgs": [],
+                "env": [["BUILD_MARKER", "1"]],
+                "out_dir": "/work/app/target/release/build/app-0123/out"
+            })
+            .to_string(),
+            artifact(app, "build-script-build", "custom-build", vec![]),
+            artifact(app, "app", "bin", vec![exe.clone()]),
+            "output of a build script".into(),
+            json!({ "reason": "build-finished", "success": true }).to_string(),
+        ] {
+            messages.handle_line(line);
+        }
+
+        assert!(messages.success);
+        assert_eq!(messages.progress.count(), 2);
+        assert_eq!(messages.progress.line("app"), "2/2 crates compiled: app");
+
+        assert_eq!(messages.binaries.len(), 1);
+        let bin = &messages.binaries[0];
+        assert_eq!(bin.path, PathBuf::from(exe));
+        assert_eq!(bin.crate_name, "app");
+        assert_eq!(
+            bin.build_script.as_ref().unwrap().env,
+            [("BUILD_MARKER".to_string(), "1".to_string())]
+        );
+    }
+
+    fn compiler_message(package_id: &str, name: &str, level: &str) -> String {
+        json!({
+            "reason": "compiler-message",
+            "package_id": package_id,
+            "manifest_path": "/work/app/Cargo.toml",
+            "target": target(name, "lib"),
+            "message": {
+                "message": "unused variable",
+                "code": null,
+                "level": level,
+                "spans": [],
+                "children": [],
+                "rendered": null
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn count_diagnostics() {
+        let app = "app 0.1.0 (path+file:///work/app)";
+        let util = "util 0.1.0 (path+file:///work/util)";
+
+        let mut messages = BuildMessages::new(Progress::new(None, false));
+        for line in [
+            compiler_message(app, "app", "warning"),
+            compiler_message(util, "util", "error"),
+            compiler_message(app, "app", "warning"),
+            compiler_message(util, "util", "warning"),
+            compiler_message(app, "app", "error: internal compiler error"),
+            // Not counted.
+            compiler_message(app, "app", "note"),
+            compiler_message(util, "util", "failure-note"),
+        ] {
+            messages.handle_line(line);
+        }
+
+        assert_eq!(
+            messages.diagnostics.lines(),
+            [
+                "`app` generated 2 warnings and 1 error",
+                "`util` generated 1 warning and 1 error",
+            ]
+        );
+
+        let mut clean = BuildMessages::new(Progress::new(None, false));
+        clean.handle_line(compiler_message(app, "app", "help"));
+        assert!(clean.diagnostics.lines().is_empty());
+    }
+}
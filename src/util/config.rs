@@ -0,0 +1,45 @@
+//! Configuration file of ddt, which is `ddt.toml` in the workspace root.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+
+use super::cargo_build::cargo_workspace_dir;
+
+pub const CONFIG_FILE_NAME: &str = "ddt.toml";
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(cargo_workspace_dir()?.join(CONFIG_FILE_NAME))
+}
+
+/// Loads a top-level table of the config file.
+///
+/// Returns the default value if the file or the table does not exist.
+pub fn load_config_section<T>(section: &str) -> Result<T>
+where
+    T: DeserializeOwned + Default,
+{
+    let path = config_path()?;
+
+    if !path.is_file() {
+        return Ok(T::default());
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let config: serde_json::Value = toml_edit::de::from_str(&content)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    match config.get(section) {
+        Some(value) => serde_json::from_value(value.clone()).with_context(|| {
+            format!(
+                "failed to parse the `[{}]` table of {}",
+                section,
+                path.display()
+            )
+        }),
+        None => Ok(T::default()),
+    }
+}
@@ -0,0 +1,402 @@
+//! Dependency graph built from the `resolve` section of `cargo metadata`.
+
+use anyhow::{Context, Result};
+use cargo_metadata::{Metadata, Node, NodeDep, Package, PackageId};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+pub struct DepGraph<'a> {
+    packages: FxHashMap<&'a PackageId, &'a Package>,
+    nodes: FxHashMap<&'a PackageId, &'a Node>,
+    workspace_members: FxHashSet<&'a PackageId>,
+    /// Package id to the list of edges pointing to it.
+    dependents: FxHashMap<&'a PackageId, Vec<Edge<'a>>>,
+}
+
+/// `from` depends on `to`.
+#[derive(Debug, Clone, Copy)]
+pub struct Edge<'a> {
+    pub from: &'a PackageId,
+    pub to: &'a PackageId,
+    pub dep: &'a NodeDep,
+}
+
+impl<'a> DepGraph<'a> {
+    pub fn new(md: &'a Metadata) -> Result<Self> {
+        let resolve = md
+            .resolve
+            .as_ref()
+            .context("cargo metadata did not resolve the dependency graph")?;
+
+        let packages = md.packages.iter().map(|p| (&p.id, p)).collect();
+        let nodes = resolve.nodes.iter().map(|n| (&n.id, n)).collect();
+        let workspace_members = md.workspace_members.iter().collect();
+
+        let mut dependents = FxHashMap::<_, Vec<_>>::default();
+        for node in &resolve.nodes {
+            for dep in &node.deps {
+                dependents.entry(&dep.pkg).or_default().push(Edge {
+                    from: &node.id,
+                    to: &dep.pkg,
+                    dep,
+                });
+            }
+        }
+
+        Ok(Self {
+            packages,
+            nodes,
+            workspace_members,
+            dependents,
+        })
+    }
+
+    pub fn package(&self, id: &PackageId) -> &'a Package {
+        self.packages[id]
+    }
+
+    /// Features enabled for the package.
+    pub fn enabled_features(&self, id: &PackageId) -> &'a [String] {
+        self.nodes.get(id).map_or(&[], |n| &n.features[..])
+    }
+
+    pub fn packages(&self) -> impl Iterator<Item = &'a Package> + '_ {
+        self.packages.values().copied()
+    }
+
+    pub fn is_workspace_member(&self, id: &PackageId) -> bool {
+        self.workspace_members.contains(id)
+    }
+
+    /// Packages `id` depends on.
+    pub fn dependencies(&self, id: &PackageId) -> impl Iterator<Item = &'a PackageId> + 'a {
+        self.edges_from(id).map(|e| e.to)
+    }
+
+    /// Edges from `id`.
+    pub fn edges_from(&self, id: &PackageId) -> impl Iterator<Item = Edge<'a>> + 'a {
+        let node = self.nodes.get(id).copied();
+
+        node.into_iter().flat_map(|n| {
+            n.deps.iter().map(move |dep| Edge {
+                from: &n.id,
+                to: &dep.pkg,
+                dep,
+            })
+        })
+    }
+
+    /// Edges pointing to `id`.
+    pub fn dependents(&self, id: &PackageId) -> &[Edge<'a>] {
+        self.dependents.get(id).map_or(&[], |v| &v[..])
+    }
+
+    /// Returns the paths from workspace members to `target`, up to `limit`
+    /// paths.
+    ///
+    /// Each path is ordered from the workspace member to `target`. A path
+    /// stops at the first workspace member, so the paths going through another
+    /// workspace member are not duplicated.
+    pub fn paths_to(&self, target: &'a PackageId, limit: usize) -> Vec<Vec<Edge<'a>>> {
+        self.paths_to_with(target, limit, |_| true)
+    }
+
+    /// Same as [DepGraph::paths_to], but only the edges accepted by `filter`
+    /// are followed.
+    pub fn paths_to_with(
+        &self,
+        target: &'a PackageId,
+        limit: usize,
+        filter: impl Fn(&Edge<'a>) -> bool,
+    ) -> Vec<Vec<Edge<'a>>> {
+        let mut paths = vec![];
+        let mut stack = vec![];
+        let mut visiting = FxHashSet::default();
+
+        self.collect_paths(
+            target,
+            limit,
+            &filter,
+            &mut stack,
+            &mut visiting,
+            &mut paths,
+        );
+
+        paths
+    }
+
+    fn collect_paths(
+        &self,
+        id: &'a PackageId,
+        limit: usize,
+        filter: &dyn Fn(&Edge<'a>) -> bool,
+        stack: &mut Vec<Edge<'a>>,
+        visiting: &mut FxHashSet<&'a PackageId>,
+        paths: &mut Vec<Vec<Edge<'a>>>,
+    ) {
+        if paths.len() >= limit {
+            return;
+        }
+
+        if self.is_workspace_member(id) {
+            paths.push(stack.iter().rev().copied().collect());
+            return;
+        }
+
+        if !visiting.insert(id) {
+            return;
+        }
+
+        for edge in self.dependents(id) {
+            if !filter(edge) {
+                continue;
+            }
+
+            stack.push(*edge);
+            self.collect_paths(edge.from, limit, filter, stack, visiting, paths);
+            stack.pop();
+        }
+
+        visiting.remove(id);
+    }
+}
+
+/// Hand-built `cargo metadata` for the tests.
+#[cfg(test)]
+pub(crate) mod fixture {
+    use cargo_metadata::{Metadata, PackageId};
+    use serde_json::{json, Value};
+
+    pub const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    /// Where a package of the fixture comes from.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum From {
+        Member,
+        CratesIo,
+        Git,
+    }
+
+    fn id_of(name: &str, version: &str, from: From) -> String {
+        match from {
+            From::Member => format!("path+file:///work/{}#{}", name, version),
+            From::CratesIo => format!("{}#{}@{}", CRATES_IO, name, version),
+            From::Git => format!("git+https://github.com/example/{}#{}", name, version),
+        }
+    }
+
+    /// Builds the metadata of `packages`, which are `(name, version, from)`.
+    ///
+    /// `deps` are `("name@version", "name@version", kind)`, where `kind` is
+    /// `normal`, `build` or `dev`. The requirement is `^version`.
+    pub fn metadata(packages: &[(&str, &str, From)], deps: &[(&str, &str, &str)]) -> Metadata {
+        let find = |key: &str| {
+            let (name, version) = key.split_once('@').unwrap();
+            let (_, _, from) = packages
+                .iter()
+                .find(|(n, v, _)| *n == name && *v == version)
+                .unwrap_or_else(|| panic!("unknown package {}", key));
+            (
+                name.to_string(),
+                version.to_string(),
+                id_of(name, version, *from),
+            )
+        };
+
+        let deps_of = |name: &str, version: &str| {
+            deps.iter()
+                .filter(|(from, _, _)| *from == format!("{}@{}", name, version))
+                .map(|(_, to, kind)| (find(to), *kind))
+                .collect::<Vec<_>>()
+        };
+
+        let kind = |kind: &str| match kind {
+            "normal" => Value::Null,
+            kind => kind.into(),
+        };
+
+        let json_packages = packages
+            .iter()
+            .map(|&(name, version, from)| {
+                json!({
+                    "name": name,
+                    "version": version,
+                    "id": id_of(name, version, from),
+                    "source": match from {
+                        From::Member => Value::Null,
+                        From::CratesIo => CRATES_IO.into(),
+                        From::Git => format!("git+https://github.com/example/{}", name).into(),
+                    },
+                    "license": "MIT",
+                    "dependencies": deps_of(name, version)
+                        .into_iter()
+                        .map(|((dep, dep_version, _), k)| json!({
+                            "name": dep,
+                            "source": CRATES_IO,
+                            "req": format!("^{}", dep_version),
+                            "kind": kind(k),
+                            "optional": false,
+                            "uses_default_features": true,
+                            "features": [],
+                        }))
+                        .collect::<Vec<_>>(),
+                    "targets": [],
+                    "features": {},
+                    "manifest_path": format!("/work/{}/Cargo.toml", name),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let nodes = packages
+            .iter()
+            .map(|&(name, version, from)| {
+                let deps = deps_of(name, version);
+                json!({
+                    "id": id_of(name, version, from),
+                    "dependencies": deps.iter().map(|((_, _, id), _)| id).collect::<Vec<_>>(),
+                    "deps": deps
+                        .iter()
+                        .map(|((dep, _, id), k)| json!({
+                            "name": dep,
+                            "pkg": id,
+                            "dep_kinds": [{ "kind": kind(k), "target": null }],
+                        }))
+                        .collect::<Vec<_>>(),
+                    "features": [],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let members = packages
+            .iter()
+            .filter(|(_, _, from)| *from == From::Member)
+            .map(|&(name, version, from)| id_of(name, version, from))
+            .collect::<Vec<_>>();
+
+        serde_json::from_value(json!({
+            "packages": json_packages,
+            "workspace_members": members,
+            "resolve": { "nodes": nodes, "root": members.first() },
+            "workspace_root": "/work",
+            "target_directory": "/work/target",
+            "version": 1,
+        }))
+        .unwrap()
+    }
+
+    /// The id of `name@version` in `md`.
+    pub fn id<'a>(md: &'a Metadata, key: &str) -> &'a PackageId {
+        let (name, version) = key.split_once('@').unwrap();
+        &md.packages
+            .iter()
+            .find(|p| p.name == name && p.version.to_string() == version)
+            .unwrap_or_else(|| panic!("unknown package {}", key))
+            .id
+    }
+
+    /// A workspace with two members, where `c` exists in two versions.
+    ///
+    /// ```text
+    /// app -> a -> c 1
+    ///     -> b -(build)-> c 1
+    ///          -> c 2
+    ///     -> core -> c 1
+    /// ```
+    pub fn workspace() -> Metadata {
+        metadata(
+            &[
+                ("app", "0.1.0", From::Member),
+                ("core", "0.1.0", From::Member),
+                ("a", "1.0.0", From::CratesIo),
+                ("b", "1.0.0", From::Git),
+                ("c", "1.0.0", From::CratesIo),
+                ("c", "2.0.0", From::CratesIo),
+            ],
+            &[
+                ("app@0.1.0", "a@1.0.0", "normal"),
+                ("app@0.1.0", "b@1.0.0", "normal"),
+                ("app@0.1.0", "core@0.1.0", "normal"),
+                ("core@0.1.0", "c@1.0.0", "normal"),
+                ("a@1.0.0", "c@1.0.0", "normal"),
+                ("b@1.0.0", "c@1.0.0", "build"),
+                ("b@1.0.0", "c@2.0.0", "normal"),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cargo_metadata::DependencyKind;
+
+    use super::{fixture::*, *};
+
+    fn format(graph: &DepGraph, paths: Vec<Vec<Edge>>) -> Vec<String> {
+        let mut paths = paths
+            .iter()
+            .map(|path| {
+                let mut s = graph.package(path[0].from).name.clone();
+                for edge in path {
+                    s.push_str(" -> ");
+                    s.push_str(&graph.package(edge.to).name);
+                }
+                s
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn edges_from() {
+        let md = workspace();
+        let graph = DepGraph::new(&md).unwrap();
+
+        let mut deps = graph
+            .edges_from(id(&md, "app@0.1.0"))
+            .map(|e| graph.package(e.to).name.clone())
+            .collect::<Vec<_>>();
+        deps.sort();
+        assert_eq!(deps, ["a", "b", "core"]);
+
+        let kinds = graph
+            .edges_from(id(&md, "b@1.0.0"))
+            .map(|e| (e.to.repr.clone(), e.dep.dep_kinds[0].kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (id(&md, "c@1.0.0").repr.clone(), DependencyKind::Build),
+                (id(&md, "c@2.0.0").repr.clone(), DependencyKind::Normal),
+            ]
+        );
+
+        assert_eq!(graph.edges_from(id(&md, "c@1.0.0")).count(), 0);
+    }
+
+    #[test]
+    fn paths_to() {
+        let md = workspace();
+        let graph = DepGraph::new(&md).unwrap();
+        let c1 = id(&md, "c@1.0.0");
+
+        // The path through `core` stops at `core`, as it's a workspace member.
+        assert_eq!(
+            format(&graph, graph.paths_to(c1, usize::MAX)),
+            ["app -> a -> c", "app -> b -> c", "core -> c"]
+        );
+        assert_eq!(graph.paths_to(c1, 2).len(), 2);
+
+        let normal_only = graph.paths_to_with(c1, usize::MAX, |e| {
+            e.dep
+                .dep_kinds
+                .iter()
+                .any(|k| k.kind == DependencyKind::Normal)
+        });
+        assert_eq!(format(&graph, normal_only), ["app -> a -> c", "core -> c"]);
+
+        assert_eq!(
+            format(&graph, graph.paths_to(id(&md, "c@2.0.0"), usize::MAX)),
+            ["app -> b -> c"]
+        );
+    }
+}
@@ -1,10 +1,14 @@
 use std::{ffi::OsStr, fmt::Display, future::Future, path::Path, process::Stdio};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use tokio::process::Command;
 use tracing::info;
 
 pub mod cargo_build;
+pub mod cargo_config;
+mod cargo_messages;
+pub mod config;
+pub mod dep_graph;
 
 /// Type annotation for [anyhow::Result]
 pub async fn wrap<Fut, Ret>(op: Fut) -> Result<Ret>
@@ -14,6 +18,25 @@ where
     op.await
 }
 
+/// Runs `op` until it completes or Ctrl-C is pressed.
+///
+/// On Ctrl-C, `op` is dropped, which kills the child processes spawned with
+/// `kill_on_drop` and removes the temporary directories owned by `op`.
+pub async fn cancellable<Fut, Ret>(op: Fut) -> Result<Ret>
+where
+    Fut: Future<Output = Result<Ret>>,
+{
+    tokio::select! {
+        biased;
+
+        res = tokio::signal::ctrl_c() => {
+            res.context("failed to listen for Ctrl-C")?;
+            bail!("interrupted by Ctrl-C")
+        }
+        res = op => res,
+    }
+}
+
 pub async fn ensure_bin_exists(name: &str) -> Result<()> {
     if which::which(name).is_err() {
         Err(anyhow::anyhow!("{} is not installed", name))
@@ -76,6 +99,24 @@ impl PrettyCmd {
         }
     }
 
+    /// Like `exec`, but stderr is captured and added to the error instead of
+    /// being printed.
+    pub async fn exec_captured(&mut self) -> Result<()> {
+        info!("Running: {}\n{:?}", self.description, self.inner);
+
+        let output = self.inner.stderr(Stdio::piped()).output().await?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} failed:\n{}",
+                self.description,
+                String::from_utf8_lossy(&output.stderr)
+            ))
+        }
+    }
+
     pub async fn output(&mut self) -> Result<String> {
         info!("Running: {}\n{:?}", self.description, self.inner);
 